@@ -0,0 +1,51 @@
+use std::net::SocketAddr;
+
+use hickory_resolver::name_server::ConnectionProvider;
+use hickory_resolver::name_server::TokioConnectionProvider;
+use reqwest::dns::Name;
+use tokio::runtime::Runtime;
+
+use crate::HickoryResolver;
+use crate::ResolveError;
+
+/// BlockingHickoryResolver resolves names synchronously with a [`HickoryResolver`] on its own
+/// current-thread Tokio runtime, so that it could be used in synchronous contexts.
+///
+/// ```no_run
+/// use reqwest_hickory_resolver::BlockingHickoryResolver;
+/// use reqwest_hickory_resolver::HickoryResolver;
+///
+/// let resolver = BlockingHickoryResolver::new(HickoryResolver::default()).unwrap();
+/// let addrs = resolver.resolve_blocking("example.com").unwrap();
+/// ```
+#[derive(Debug)]
+pub struct BlockingHickoryResolver<P: ConnectionProvider = TokioConnectionProvider> {
+    resolver: HickoryResolver<P>,
+    runtime: Runtime,
+}
+
+impl<P: ConnectionProvider> BlockingHickoryResolver<P> {
+    /// Create a new blocking resolver with the given [`HickoryResolver`], all its options
+    /// are respected.
+    pub fn new(resolver: HickoryResolver<P>) -> Result<Self, ResolveError> {
+        let runtime = tokio::runtime::Builder::new_current_thread()
+            .enable_all()
+            .build()?;
+
+        Ok(BlockingHickoryResolver { resolver, runtime })
+    }
+
+    /// Resolve the given name the same way as [`reqwest::dns::Resolve::resolve`], blocking
+    /// the current thread until finished.
+    ///
+    /// NOTES: this will panic if called within an async context.
+    pub fn resolve_blocking(&self, name: &str) -> Result<Vec<SocketAddr>, ResolveError> {
+        let name = name
+            .parse::<Name>()
+            .map_err(|err| ResolveError::Other(err.to_string()))?;
+        let mut resolver = self.resolver.clone();
+
+        self.runtime
+            .block_on(async move { Ok(resolver.resolve_name(&name, false).await?.collect()) })
+    }
+}
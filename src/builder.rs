@@ -0,0 +1,496 @@
+use std::net::IpAddr;
+use std::net::Ipv6Addr;
+use std::net::SocketAddr;
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::time::Duration;
+
+use hickory_resolver::config::LookupIpStrategy;
+use hickory_resolver::config::ResolverConfig;
+use hickory_resolver::config::ResolverOpts;
+use hickory_resolver::name_server::ConnectionProvider;
+use hickory_resolver::name_server::TokioConnectionProvider;
+use ipnet::IpNet;
+
+use crate::Backoff;
+use crate::DnsCache;
+use crate::DnsProtocol;
+use crate::FamilyPreference;
+use crate::HickoryResolver;
+use crate::NameRewrite;
+use crate::ResolveError;
+use crate::ResolveHook;
+use crate::SlowQueryHook;
+use crate::Transport;
+
+/// HickoryResolverBuilder is the validated way to construct [`HickoryResolver`].
+///
+/// Unlike the `with_*` methods on [`HickoryResolver`], conflicting options will be reported
+/// by [`HickoryResolverBuilder::build`] instead of silently ignored.
+///
+/// ```
+/// use std::time::Duration;
+///
+/// use reqwest_hickory_resolver::HickoryResolver;
+///
+/// let resolver = HickoryResolver::builder()
+///     .nameservers(vec!["1.1.1.1:53".parse().unwrap()])
+///     .timeout(Duration::from_secs(3))
+///     .build()
+///     .unwrap();
+/// ```
+#[derive(Debug, Clone)]
+pub struct HickoryResolverBuilder<P: ConnectionProvider = TokioConnectionProvider> {
+    resolver: HickoryResolver<P>,
+}
+
+impl Default for HickoryResolverBuilder {
+    fn default() -> Self {
+        Self::from_provider(TokioConnectionProvider::default())
+    }
+}
+
+impl HickoryResolver {
+    /// Create a new [`HickoryResolverBuilder`].
+    pub fn builder() -> HickoryResolverBuilder {
+        HickoryResolverBuilder::default()
+    }
+}
+
+impl<P: ConnectionProvider> HickoryResolverBuilder<P> {
+    /// Create a new builder with the given [`ConnectionProvider`].
+    pub fn from_provider(provider: P) -> Self {
+        HickoryResolverBuilder {
+            resolver: HickoryResolver::from_provider(provider),
+        }
+    }
+
+    /// Build the [`HickoryResolver`], returns error if the options conflict with each other.
+    pub fn build(self) -> Result<HickoryResolver<P>, ResolveError> {
+        let options = &self.resolver.options;
+
+        if options.config.is_some() {
+            if options.nameservers.is_some() {
+                return Err(ResolveError::Config(
+                    "config and nameservers can't be set at the same time".to_string(),
+                ));
+            }
+            if options.protocol.is_some() {
+                return Err(ResolveError::Config(
+                    "config and protocol can't be set at the same time".to_string(),
+                ));
+            }
+        }
+        if options.protocol.is_some() && options.transport.is_some() {
+            return Err(ResolveError::Config(
+                "protocol and transport can't be set at the same time".to_string(),
+            ));
+        }
+        if options
+            .concurrency
+            .as_ref()
+            .is_some_and(|semaphore| semaphore.is_closed())
+        {
+            return Err(ResolveError::Config(
+                "max concurrency must be greater than 0".to_string(),
+            ));
+        }
+        if options.nameservers.as_ref().is_some_and(Vec::is_empty) {
+            return Err(ResolveError::Config("nameservers is empty".to_string()));
+        }
+        if options.ttl_bounds.is_some_and(|(min, max)| min > max) {
+            return Err(ResolveError::Config(
+                "min ttl must not be greater than max ttl".to_string(),
+            ));
+        }
+        if options
+            .prefetch_refresh
+            .is_some_and(|fraction| !(fraction > 0.0 && fraction <= 1.0))
+        {
+            return Err(ResolveError::Config(
+                "prefetch refresh fraction must be in (0, 1]".to_string(),
+            ));
+        }
+        if matches!(options.retry_backoff, Backoff::Exponential { base, max } if base > max) {
+            return Err(ResolveError::Config(
+                "backoff base must not be greater than max".to_string(),
+            ));
+        }
+        if options.max_addrs == Some(0) {
+            return Err(ResolveError::Config(
+                "max addrs must be greater than 0".to_string(),
+            ));
+        }
+        if options.max_addrs_per_family == Some((0, 0)) {
+            return Err(ResolveError::Config(
+                "max addrs of at least one family must be greater than 0".to_string(),
+            ));
+        }
+        if matches!((options.min_addrs, options.max_addrs), (Some(min), Some(max)) if min > max) {
+            return Err(ResolveError::Config(
+                "min addrs must not be greater than max addrs".to_string(),
+            ));
+        }
+        if options.sorted && self.resolver.is_shuffle() {
+            return Err(ResolveError::Config(
+                "sorted and shuffle can't be enabled at the same time".to_string(),
+            ));
+        }
+
+        Ok(self.resolver)
+    }
+
+    /// See [`HickoryResolver::with_config`].
+    pub fn config(mut self, config: ResolverConfig) -> Self {
+        self.resolver = self.resolver.with_config(config);
+        self
+    }
+
+    /// See [`HickoryResolver::with_fallback_config`].
+    pub fn fallback_config(mut self, config: ResolverConfig) -> Self {
+        self.resolver = self.resolver.with_fallback_config(config);
+        self
+    }
+
+    /// See [`HickoryResolver::with_options`].
+    pub fn options(mut self, opts: ResolverOpts) -> Self {
+        self.resolver = self.resolver.with_options(opts);
+        self
+    }
+
+    /// See [`HickoryResolver::with_ip_strategy`].
+    pub fn ip_strategy(mut self, strategy: LookupIpStrategy) -> Self {
+        self.resolver = self.resolver.with_ip_strategy(strategy);
+        self
+    }
+
+    /// See [`HickoryResolver::with_attempts`].
+    pub fn attempts(mut self, attempts: usize) -> Self {
+        self.resolver = self.resolver.with_attempts(attempts);
+        self
+    }
+
+    /// See [`HickoryResolver::with_rotate_nameservers`].
+    pub fn rotate_nameservers(mut self, enabled: bool) -> Self {
+        self.resolver = self.resolver.with_rotate_nameservers(enabled);
+        self
+    }
+
+    /// See [`HickoryResolver::with_cache_size`].
+    pub fn cache_size(mut self, size: usize) -> Self {
+        self.resolver = self.resolver.with_cache_size(size);
+        self
+    }
+
+    /// See [`HickoryResolver::with_preserve_intermediates`].
+    pub fn preserve_intermediates(mut self, enabled: bool) -> Self {
+        self.resolver = self.resolver.with_preserve_intermediates(enabled);
+        self
+    }
+
+    /// See [`HickoryResolver::with_negative_caching`].
+    pub fn negative_caching(mut self, enabled: bool) -> Self {
+        self.resolver = self.resolver.with_negative_caching(enabled);
+        self
+    }
+
+    /// See [`HickoryResolver::with_ttl_bounds`].
+    pub fn ttl_bounds(mut self, min: Duration, max: Duration) -> Self {
+        self.resolver = self.resolver.with_ttl_bounds(min, max);
+        self
+    }
+
+    /// See [`HickoryResolver::with_dnssec`].
+    #[cfg(feature = "dnssec")]
+    pub fn dnssec(mut self, enabled: bool) -> Self {
+        self.resolver = self.resolver.with_dnssec(enabled);
+        self
+    }
+
+    /// See [`HickoryResolver::with_ttl_override`].
+    pub fn ttl_override(mut self, name: &str, ttl: Duration) -> Self {
+        self.resolver = self.resolver.with_ttl_override(name, ttl);
+        self
+    }
+
+    /// See [`HickoryResolver::with_client_subnet`].
+    pub fn client_subnet(mut self, subnet: IpNet) -> Self {
+        self.resolver = self.resolver.with_client_subnet(subnet);
+        self
+    }
+
+    /// See [`HickoryResolver::with_search_domains`].
+    pub fn search_domains(mut self, domains: Vec<String>) -> Self {
+        self.resolver = self.resolver.with_search_domains(domains);
+        self
+    }
+
+    /// See [`HickoryResolver::with_ndots`].
+    pub fn ndots(mut self, ndots: usize) -> Self {
+        self.resolver = self.resolver.with_ndots(ndots);
+        self
+    }
+
+    /// See [`HickoryResolver::with_resolv_conf_path`].
+    #[cfg(unix)]
+    pub fn resolv_conf_path(mut self, path: PathBuf) -> Self {
+        self.resolver = self.resolver.with_resolv_conf_path(path);
+        self
+    }
+
+    /// See [`HickoryResolver::with_disable_default_fallback`].
+    #[cfg(unix)]
+    pub fn disable_default_fallback(mut self, enabled: bool) -> Self {
+        self.resolver = self.resolver.with_disable_default_fallback(enabled);
+        self
+    }
+
+    /// See [`HickoryResolver::with_nameservers`].
+    pub fn nameservers(mut self, servers: Vec<SocketAddr>) -> Self {
+        self.resolver = self.resolver.with_nameservers(servers);
+        self
+    }
+
+    /// See [`HickoryResolver::with_nameservers_str`].
+    pub fn nameservers_str(mut self, s: &str) -> Result<Self, ResolveError> {
+        self.resolver = self.resolver.with_nameservers_str(s)?;
+        Ok(self)
+    }
+
+    /// See [`HickoryResolver::with_protocol`].
+    pub fn protocol(mut self, protocol: DnsProtocol) -> Self {
+        self.resolver = self.resolver.with_protocol(protocol);
+        self
+    }
+
+    /// See [`HickoryResolver::with_transport`].
+    pub fn transport(mut self, transport: Transport) -> Self {
+        self.resolver = self.resolver.with_transport(transport);
+        self
+    }
+
+    /// See [`HickoryResolver::with_tls_dns_name`].
+    pub fn tls_dns_name(mut self, name: &str) -> Self {
+        self.resolver = self.resolver.with_tls_dns_name(name);
+        self
+    }
+
+    /// See [`HickoryResolver::with_bootstrap_nameservers`].
+    pub fn bootstrap_nameservers(mut self, servers: Vec<IpAddr>) -> Self {
+        self.resolver = self.resolver.with_bootstrap_nameservers(servers);
+        self
+    }
+
+    /// See [`HickoryResolver::with_family_preference`].
+    pub fn family_preference(mut self, pref: FamilyPreference) -> Self {
+        self.resolver = self.resolver.with_family_preference(pref);
+        self
+    }
+
+    /// See [`HickoryResolver::with_timeout`].
+    pub fn timeout(mut self, timeout: Duration) -> Self {
+        self.resolver = self.resolver.with_timeout(timeout);
+        self
+    }
+
+    /// See [`HickoryResolver::with_connect_timeout`].
+    pub fn connect_timeout(mut self, timeout: Duration) -> Self {
+        self.resolver = self.resolver.with_connect_timeout(timeout);
+        self
+    }
+
+    /// See [`HickoryResolver::with_bind_addr`].
+    pub fn bind_addr(mut self, addr: IpAddr) -> Self {
+        self.resolver = self.resolver.with_bind_addr(addr);
+        self
+    }
+
+    /// See [`HickoryResolver::with_case_randomization`].
+    pub fn case_randomization(mut self, enabled: bool) -> Self {
+        self.resolver = self.resolver.with_case_randomization(enabled);
+        self
+    }
+
+    /// See [`HickoryResolver::with_retries`].
+    pub fn retries(mut self, attempts: usize) -> Self {
+        self.resolver = self.resolver.with_retries(attempts);
+        self
+    }
+
+    /// See [`HickoryResolver::with_retry_backoff`].
+    pub fn retry_backoff(mut self, strategy: Backoff) -> Self {
+        self.resolver = self.resolver.with_retry_backoff(strategy);
+        self
+    }
+
+    /// See [`HickoryResolver::with_max_concurrency`].
+    pub fn max_concurrency(mut self, n: usize) -> Self {
+        self.resolver = self.resolver.with_max_concurrency(n);
+        self
+    }
+
+    /// See [`HickoryResolver::with_fallback`].
+    pub fn fallback(mut self, fallback: Arc<HickoryResolver<P>>) -> Self {
+        self.resolver = self.resolver.with_fallback(fallback);
+        self
+    }
+
+    /// See [`HickoryResolver::with_routed_resolver`].
+    pub fn routed_resolver(mut self, suffix: &str, resolver: Arc<HickoryResolver<P>>) -> Self {
+        self.resolver = self.resolver.with_routed_resolver(suffix, resolver);
+        self
+    }
+
+    /// See [`HickoryResolver::with_static_host`].
+    pub fn static_host(mut self, name: &str, addrs: Vec<IpAddr>) -> Self {
+        self.resolver = self.resolver.with_static_host(name, addrs);
+        self
+    }
+
+    /// See [`HickoryResolver::with_ipv6_scope`].
+    pub fn ipv6_scope(mut self, addr: Ipv6Addr, scope_id: u32) -> Self {
+        self.resolver = self.resolver.with_ipv6_scope(addr, scope_id);
+        self
+    }
+
+    /// See [`HickoryResolver::with_hosts_file`].
+    pub fn hosts_file(mut self, path: PathBuf) -> Result<Self, ResolveError> {
+        self.resolver = self.resolver.with_hosts_file(path)?;
+        Ok(self)
+    }
+
+    /// See [`HickoryResolver::with_blocklist`].
+    pub fn blocklist(mut self, names: Vec<String>) -> Self {
+        self.resolver = self.resolver.with_blocklist(names);
+        self
+    }
+
+    /// See [`HickoryResolver::with_allowlist`].
+    pub fn allowlist(mut self, names: Vec<String>) -> Self {
+        self.resolver = self.resolver.with_allowlist(names);
+        self
+    }
+
+    /// See [`HickoryResolver::with_shuffle`].
+    #[cfg(feature = "shuffle")]
+    pub fn shuffle(mut self, shuffle: bool) -> Self {
+        self.resolver = self.resolver.with_shuffle(shuffle);
+        self
+    }
+
+    /// See [`HickoryResolver::with_shuffle_seed`].
+    #[cfg(feature = "shuffle")]
+    pub fn shuffle_seed(mut self, seed: u64) -> Self {
+        self.resolver = self.resolver.with_shuffle_seed(seed);
+        self
+    }
+
+    /// See [`HickoryResolver::with_rng`].
+    #[cfg(feature = "shuffle")]
+    pub fn rng(mut self, rng: crate::BoxedRng) -> Self {
+        self.resolver = self.resolver.with_rng(rng);
+        self
+    }
+
+    /// See [`HickoryResolver::with_rotation`].
+    pub fn rotation(mut self, enabled: bool) -> Self {
+        self.resolver = self.resolver.with_rotation(enabled);
+        self
+    }
+
+    /// See [`HickoryResolver::with_sticky`].
+    pub fn sticky(mut self, enabled: bool) -> Self {
+        self.resolver = self.resolver.with_sticky(enabled);
+        self
+    }
+
+    /// See [`HickoryResolver::with_latency_feedback`].
+    pub fn latency_feedback(mut self, enabled: bool) -> Self {
+        self.resolver = self.resolver.with_latency_feedback(enabled);
+        self
+    }
+
+    /// See [`HickoryResolver::with_sorted`].
+    pub fn sorted(mut self, enabled: bool) -> Self {
+        self.resolver = self.resolver.with_sorted(enabled);
+        self
+    }
+
+    /// See [`HickoryResolver::with_reject_private`].
+    pub fn reject_private(mut self, enabled: bool) -> Self {
+        self.resolver = self.resolver.with_reject_private(enabled);
+        self
+    }
+
+    /// See [`HickoryResolver::with_denied_cidrs`].
+    pub fn denied_cidrs(mut self, cidrs: Vec<IpNet>) -> Self {
+        self.resolver = self.resolver.with_denied_cidrs(cidrs);
+        self
+    }
+
+    /// See [`HickoryResolver::with_interleave`].
+    pub fn interleave(mut self, enabled: bool) -> Self {
+        self.resolver = self.resolver.with_interleave(enabled);
+        self
+    }
+
+    /// See [`HickoryResolver::with_preserve_order`].
+    pub fn preserve_order(mut self, enabled: bool) -> Self {
+        self.resolver = self.resolver.with_preserve_order(enabled);
+        self
+    }
+
+    /// See [`HickoryResolver::with_dedup`].
+    pub fn dedup(mut self, enabled: bool) -> Self {
+        self.resolver = self.resolver.with_dedup(enabled);
+        self
+    }
+
+    /// See [`HickoryResolver::with_min_addrs`].
+    pub fn min_addrs(mut self, n: usize) -> Self {
+        self.resolver = self.resolver.with_min_addrs(n);
+        self
+    }
+
+    /// See [`HickoryResolver::with_max_addrs`].
+    pub fn max_addrs(mut self, n: usize) -> Self {
+        self.resolver = self.resolver.with_max_addrs(n);
+        self
+    }
+
+    /// See [`HickoryResolver::with_max_addrs_per_family`].
+    pub fn max_addrs_per_family(mut self, v4: usize, v6: usize) -> Self {
+        self.resolver = self.resolver.with_max_addrs_per_family(v4, v6);
+        self
+    }
+
+    /// See [`HickoryResolver::with_cache`].
+    pub fn cache(mut self, cache: Arc<dyn DnsCache>) -> Self {
+        self.resolver = self.resolver.with_cache(cache);
+        self
+    }
+
+    /// See [`HickoryResolver::with_prefetch_refresh`].
+    pub fn prefetch_refresh(mut self, fraction: f32) -> Self {
+        self.resolver = self.resolver.with_prefetch_refresh(fraction);
+        self
+    }
+
+    /// See [`HickoryResolver::with_name_rewrite`].
+    pub fn name_rewrite(mut self, rewrite: NameRewrite) -> Self {
+        self.resolver = self.resolver.with_name_rewrite(rewrite);
+        self
+    }
+
+    /// See [`HickoryResolver::with_on_resolve`].
+    pub fn on_resolve(mut self, hook: ResolveHook) -> Self {
+        self.resolver = self.resolver.with_on_resolve(hook);
+        self
+    }
+
+    /// See [`HickoryResolver::with_slow_query_threshold`].
+    pub fn slow_query_threshold(mut self, threshold: Duration, hook: SlowQueryHook) -> Self {
+        self.resolver = self.resolver.with_slow_query_threshold(threshold, hook);
+        self
+    }
+}
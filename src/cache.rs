@@ -0,0 +1,527 @@
+use std::collections::hash_map::RandomState;
+use std::collections::HashMap;
+use std::fmt::Write;
+use std::hash::BuildHasher;
+use std::hash::Hasher;
+use std::net::IpAddr;
+use std::path::Path;
+use std::sync::atomic::AtomicU64;
+use std::sync::atomic::Ordering;
+use std::sync::RwLock;
+use std::time::Duration;
+use std::time::Instant;
+use std::time::SystemTime;
+use std::time::UNIX_EPOCH;
+
+use futures::future::BoxFuture;
+use hickory_resolver::lookup_ip::LookupIp;
+
+use crate::normalize_name;
+use crate::ResolveError;
+
+/// DnsCache is the cache consulted by [`HickoryResolver`](crate::HickoryResolver) before
+/// querying the name servers, see
+/// [`HickoryResolver::with_cache`](crate::HickoryResolver::with_cache).
+///
+/// The hickory's internal cache is still used underneath, so implementations are free
+/// to be lossy.
+pub trait DnsCache: Send + Sync + 'static {
+    /// Get the cached ip addrs of the given name, returns `None` if not cached or expired.
+    fn get<'a>(&'a self, name: &'a str) -> BoxFuture<'a, Option<Vec<IpAddr>>>;
+
+    /// Put the ip addrs of the given name which are valid for `ttl`.
+    fn put<'a>(&'a self, name: &'a str, addrs: Vec<IpAddr>, ttl: Duration) -> BoxFuture<'a, ()>;
+}
+
+/// CacheStats is the statistics of the cache returned by
+/// [`HickoryResolver::cache_stats`](crate::HickoryResolver::cache_stats).
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct CacheStats {
+    /// The number of resolutions served from the cache.
+    pub hits: u64,
+    /// The number of resolutions that need to query the name servers.
+    pub misses: u64,
+}
+
+/// CacheIndex is the shadow index of the names cached by the underlying resolver.
+///
+/// hickory doesn't expose its cache, so we record the `valid_until` and addrs of
+/// every successful lookup instead. The same as the underlying cache, there are at most
+/// `capacity` names, the expired ones and then the ones expiring soonest are evicted to
+/// make room for new ones.
+///
+/// The index is consulted on every resolution, so the names are looked up without
+/// allocation and the cache hits only take the read locks.
+#[derive(Debug, Default)]
+pub(crate) struct CacheIndex {
+    entries: RwLock<HashMap<String, CacheEntry>>,
+    /// The time each negatively cached name is valid until.
+    negatives: RwLock<HashMap<String, Instant>>,
+    hits: AtomicU64,
+    misses: AtomicU64,
+}
+
+#[derive(Debug)]
+struct CacheEntry {
+    valid_until: Instant,
+    /// The TTL at the time this entry is inserted.
+    ttl: Duration,
+    addrs: Vec<IpAddr>,
+    /// Whether this entry is detached from the cache of the underlying resolver, for
+    /// example restored by [`CacheIndex::load`]. Detached entries are served by us.
+    detached: bool,
+    /// Whether a background refresh of this entry is in progress.
+    refreshing: bool,
+}
+
+impl CacheIndex {
+    pub(crate) fn insert(&self, name: &str, lookup: &LookupIp, capacity: usize) {
+        self.insert_entry(name, lookup, lookup.valid_until(), false, capacity);
+    }
+
+    /// Insert the lookup as a detached entry which will be served until `valid_until`.
+    pub(crate) fn insert_detached(
+        &self,
+        name: &str,
+        lookup: &LookupIp,
+        valid_until: Instant,
+        capacity: usize,
+    ) {
+        self.insert_entry(name, lookup, valid_until, true, capacity);
+    }
+
+    fn insert_entry(
+        &self,
+        name: &str,
+        lookup: &LookupIp,
+        valid_until: Instant,
+        detached: bool,
+        capacity: usize,
+    ) {
+        let name = normalize_name(name);
+        // The same answer served from the cache again is already indexed.
+        {
+            let entries = self.entries.read().expect("lock must be valid");
+            let indexed = entries.get(name.as_ref()).is_some_and(|entry| {
+                entry.valid_until == valid_until && entry.detached == detached
+            });
+            if indexed {
+                return;
+            }
+        }
+
+        self.negatives
+            .write()
+            .expect("lock must be valid")
+            .remove(name.as_ref());
+        let mut entries = self.entries.write().expect("lock must be valid");
+        if !make_room(&mut entries, &name, capacity, |entry| entry.valid_until) {
+            return;
+        }
+        // The same answer served from the cache again keeps its original TTL and state.
+        let (ttl, refreshing) = match entries.get(name.as_ref()) {
+            Some(entry) if entry.valid_until == valid_until => (entry.ttl, entry.refreshing),
+            _ => (valid_until.saturating_duration_since(Instant::now()), false),
+        };
+        let entry = CacheEntry {
+            valid_until,
+            ttl,
+            addrs: lookup.iter().collect(),
+            detached,
+            refreshing,
+        };
+        entries.insert(name.into_owned(), entry);
+    }
+
+    /// Record the given name as negatively cached until `valid_until`.
+    pub(crate) fn insert_negative(&self, name: &str, valid_until: Instant, capacity: usize) {
+        let name = normalize_name(name);
+        let mut negatives = self.negatives.write().expect("lock must be valid");
+        if make_room(&mut negatives, &name, capacity, |valid_until| *valid_until) {
+            negatives.insert(name.into_owned(), valid_until);
+        }
+    }
+
+    /// Returns the remaining time of the given name being negatively cached.
+    pub(crate) fn negative_ttl_remaining(&self, name: &str) -> Option<Duration> {
+        let now = Instant::now();
+        let negatives = self.negatives.read().expect("lock must be valid");
+        negatives
+            .get(normalize_name(name).as_ref())
+            .filter(|valid_until| **valid_until > now)
+            .map(|valid_until| *valid_until - now)
+    }
+
+    /// Returns whether the given name has an entry that is not expired yet.
+    pub(crate) fn is_fresh(&self, name: &str) -> bool {
+        let entries = self.entries.read().expect("lock must be valid");
+        entries
+            .get(normalize_name(name).as_ref())
+            .is_some_and(|entry| entry.valid_until > Instant::now())
+    }
+
+    /// Returns whether the given name should be refreshed in background, that is its
+    /// remaining TTL is within the jittered `fraction` of the TTL.
+    ///
+    /// The entry is marked as refreshing so that only one refresh is kicked off, the mark
+    /// is cleared by the refreshed entry or [`CacheIndex::cancel_refresh`].
+    pub(crate) fn start_refresh(&self, name: &str, fraction: f32) -> bool {
+        let now = Instant::now();
+        let mut entries = self.entries.write().expect("lock must be valid");
+        let Some(entry) = entries.get_mut(normalize_name(name).as_ref()) else {
+            return false;
+        };
+        if entry.refreshing || entry.valid_until <= now {
+            return false;
+        }
+
+        // Jitter the threshold in [fraction / 2, fraction] so that the entries inserted at
+        // the same time won't be refreshed at once.
+        let jitter = RandomState::new().build_hasher().finish() as f64 / u64::MAX as f64;
+        let threshold = entry
+            .ttl
+            .mul_f64(f64::from(fraction) * (0.5 + jitter / 2.0));
+        if entry.valid_until - now > threshold {
+            return false;
+        }
+        entry.refreshing = true;
+        true
+    }
+
+    /// Clear the refreshing mark of the given name after a failed refresh.
+    pub(crate) fn cancel_refresh(&self, name: &str) {
+        let mut entries = self.entries.write().expect("lock must be valid");
+        if let Some(entry) = entries.get_mut(normalize_name(name).as_ref()) {
+            entry.refreshing = false;
+        }
+    }
+
+    /// Record an access of the given name as either hit or miss, returns whether it's a
+    /// hit along with the addrs if it's detached, which should be served by us.
+    pub(crate) fn record_access(&self, name: &str) -> (bool, Option<Vec<IpAddr>>) {
+        let (fresh, detached) = {
+            let entries = self.entries.read().expect("lock must be valid");
+            match entries
+                .get(normalize_name(name).as_ref())
+                .filter(|entry| entry.valid_until > Instant::now())
+            {
+                Some(entry) => (true, entry.detached.then(|| entry.addrs.clone())),
+                None => (false, None),
+            }
+        };
+        let counter = if fresh { &self.hits } else { &self.misses };
+        counter.fetch_add(1, Ordering::Relaxed);
+        (fresh, detached)
+    }
+
+    pub(crate) fn stats(&self) -> CacheStats {
+        CacheStats {
+            hits: self.hits.load(Ordering::Relaxed),
+            misses: self.misses.load(Ordering::Relaxed),
+        }
+    }
+
+    /// Returns the number of entries that are not expired yet.
+    pub(crate) fn len(&self) -> usize {
+        let now = Instant::now();
+        let entries = self.entries.read().expect("lock must be valid");
+        entries
+            .values()
+            .filter(|entry| entry.valid_until > now)
+            .count()
+    }
+
+    pub(crate) fn clear(&self) {
+        self.entries.write().expect("lock must be valid").clear();
+        self.negatives.write().expect("lock must be valid").clear();
+    }
+
+    /// Save the entries that are not expired yet into the given file.
+    ///
+    /// Each line is in the form of `name expires_at ip[,ip...]` where `expires_at`
+    /// is the unix timestamp in seconds.
+    pub(crate) fn save(&self, path: &Path) -> Result<(), ResolveError> {
+        let (now, system_now) = (Instant::now(), SystemTime::now());
+        let mut content = String::new();
+        {
+            let entries = self.entries.read().expect("lock must be valid");
+            for (name, entry) in entries.iter() {
+                if entry.valid_until <= now || entry.addrs.is_empty() {
+                    continue;
+                }
+                let expires_at = (system_now + (entry.valid_until - now))
+                    .duration_since(UNIX_EPOCH)
+                    .unwrap_or_default()
+                    .as_secs();
+                let addrs = entry
+                    .addrs
+                    .iter()
+                    .map(IpAddr::to_string)
+                    .collect::<Vec<_>>()
+                    .join(",");
+                writeln!(content, "{name} {expires_at} {addrs}").expect("write to string");
+            }
+        }
+
+        Ok(std::fs::write(path, content)?)
+    }
+
+    /// Load the entries saved by [`CacheIndex::save`], the expired ones are discarded.
+    pub(crate) fn load(&self, path: &Path, capacity: usize) -> Result<(), ResolveError> {
+        let content = std::fs::read_to_string(path)?;
+        let (now, system_now) = (Instant::now(), SystemTime::now());
+
+        let mut loaded = Vec::new();
+        for (idx, line) in content.lines().enumerate() {
+            let err = || {
+                ResolveError::Config(format!(
+                    "invalid cache file {} at line {}",
+                    path.display(),
+                    idx + 1
+                ))
+            };
+            if line.trim().is_empty() {
+                continue;
+            }
+
+            let mut fields = line.split_whitespace();
+            let (Some(name), Some(expires_at), Some(addrs), None) =
+                (fields.next(), fields.next(), fields.next(), fields.next())
+            else {
+                return Err(err());
+            };
+            let expires_at =
+                UNIX_EPOCH + Duration::from_secs(expires_at.parse::<u64>().map_err(|_| err())?);
+            let addrs = addrs
+                .split(',')
+                .map(|ip| ip.parse::<IpAddr>().map_err(|_| err()))
+                .collect::<Result<Vec<_>, _>>()?;
+
+            let Ok(ttl) = expires_at.duration_since(system_now) else {
+                continue;
+            };
+            loaded.push((normalize_name(name).into_owned(), now + ttl, addrs));
+        }
+
+        let mut entries = self.entries.write().expect("lock must be valid");
+        for (name, valid_until, addrs) in loaded {
+            // Entries from the underlying resolver are preferred.
+            if entries
+                .get(&name)
+                .is_some_and(|entry| entry.valid_until > now)
+            {
+                continue;
+            }
+            if !make_room(&mut entries, &name, capacity, |entry| entry.valid_until) {
+                break;
+            }
+            let entry = CacheEntry {
+                valid_until,
+                ttl: valid_until - now,
+                addrs,
+                detached: true,
+                refreshing: false,
+            };
+            entries.insert(name, entry);
+        }
+        Ok(())
+    }
+}
+
+/// Make room for the given name so that there are at most `capacity` names after it's
+/// inserted, the expired ones are pruned first and then the ones expiring soonest.
+///
+/// Returns false if there is no room at all.
+fn make_room<V>(
+    map: &mut HashMap<String, V>,
+    name: &str,
+    capacity: usize,
+    valid_until: impl Fn(&V) -> Instant,
+) -> bool {
+    if capacity == 0 {
+        return false;
+    }
+    if map.len() < capacity || map.contains_key(name) {
+        return true;
+    }
+
+    let now = Instant::now();
+    map.retain(|_, v| valid_until(v) > now);
+    while map.len() >= capacity {
+        let Some(soonest) = map
+            .iter()
+            .min_by_key(|(_, v)| valid_until(v))
+            .map(|(name, _)| name.clone())
+        else {
+            break;
+        };
+        map.remove(&soonest);
+    }
+    true
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use hickory_resolver::lookup::Lookup;
+    use hickory_resolver::proto::op::Query;
+    use hickory_resolver::proto::rr::rdata::A;
+    use hickory_resolver::proto::rr::RData;
+    use hickory_resolver::proto::rr::Record;
+    use hickory_resolver::proto::rr::RecordType;
+    use hickory_resolver::Name;
+
+    use super::*;
+
+    const CAPACITY: usize = 32;
+
+    fn lookup(name: &str, ips: &[&str], ttl: Duration) -> LookupIp {
+        let name = Name::from_ascii(name).unwrap();
+        let records = ips
+            .iter()
+            .map(|ip| Record::from_rdata(name.clone(), 60, RData::A(A(ip.parse().unwrap()))))
+            .collect::<Vec<_>>();
+        let query = Query::query(name, RecordType::A);
+        Lookup::new_with_deadline(query, Arc::from(records), Instant::now() + ttl).into()
+    }
+
+    #[test]
+    fn test_save_and_load() {
+        let path = std::env::temp_dir().join(format!("hickory-cache-{}", std::process::id()));
+        let index = CacheIndex::default();
+        index.insert(
+            "A.test",
+            &lookup(
+                "a.test.",
+                &["10.0.0.1", "10.0.0.2"],
+                Duration::from_secs(60),
+            ),
+            CAPACITY,
+        );
+        index.insert(
+            "expired.test",
+            &lookup("expired.test.", &["10.0.0.3"], Duration::ZERO),
+            CAPACITY,
+        );
+        index.save(&path).unwrap();
+
+        let content = std::fs::read_to_string(&path).unwrap();
+        assert_eq!(content.lines().count(), 1);
+        assert!(content.starts_with("a.test "));
+        assert!(content.trim_end().ends_with(" 10.0.0.1,10.0.0.2"));
+
+        let loaded = CacheIndex::default();
+        loaded.load(&path, CAPACITY).unwrap();
+        assert_eq!(
+            loaded.record_access("a.test").1,
+            Some(vec![
+                "10.0.0.1".parse().unwrap(),
+                "10.0.0.2".parse().unwrap()
+            ])
+        );
+        assert_eq!(loaded.record_access("expired.test").1, None);
+
+        // The expired entries in the file are discarded.
+        std::fs::write(&path, "old.test 1 10.0.0.1\n").unwrap();
+        let loaded = CacheIndex::default();
+        loaded.load(&path, CAPACITY).unwrap();
+        assert_eq!(loaded.len(), 0);
+
+        for content in [
+            "a.test 1\n",
+            "a.test soon 10.0.0.1\n",
+            "a.test 1 not-an-ip\n",
+        ] {
+            std::fs::write(&path, content).unwrap();
+            let res = CacheIndex::default().load(&path, CAPACITY);
+            assert!(
+                matches!(&res, Err(ResolveError::Config(msg)) if msg.contains("line 1")),
+                "{content}: {res:?}"
+            );
+        }
+        std::fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn test_start_refresh() {
+        let index = CacheIndex::default();
+        assert!(!index.start_refresh("a.test", 1.0));
+
+        index.insert(
+            "a.test",
+            &lookup("a.test.", &["10.0.0.1"], Duration::from_millis(200)),
+            CAPACITY,
+        );
+        // Far from the expiry.
+        assert!(!index.start_refresh("a.test", 0.1));
+        // Within half of the TTL, but only one refresh is kicked off.
+        std::thread::sleep(Duration::from_millis(110));
+        assert!(index.start_refresh("a.test", 1.0));
+        assert!(!index.start_refresh("a.test", 1.0));
+        index.cancel_refresh("a.test");
+        assert!(index.start_refresh("a.test", 1.0));
+
+        index.insert(
+            "expired.test",
+            &lookup("expired.test.", &["10.0.0.1"], Duration::ZERO),
+            CAPACITY,
+        );
+        assert!(!index.start_refresh("expired.test", 1.0));
+    }
+
+    #[test]
+    fn test_capacity() {
+        let index = CacheIndex::default();
+        index.insert(
+            "expired.test",
+            &lookup("expired.test.", &["10.0.0.1"], Duration::ZERO),
+            2,
+        );
+        index.insert(
+            "a.test",
+            &lookup("a.test.", &["10.0.0.1"], Duration::from_secs(60)),
+            2,
+        );
+        // The expired entry is evicted first.
+        index.insert(
+            "b.test",
+            &lookup("b.test.", &["10.0.0.1"], Duration::from_secs(30)),
+            2,
+        );
+        assert!(index.is_fresh("a.test") && index.is_fresh("b.test"));
+        assert_eq!(index.entries.read().unwrap().len(), 2);
+        // Then the one expiring soonest.
+        index.insert(
+            "c.test",
+            &lookup("c.test.", &["10.0.0.1"], Duration::from_secs(90)),
+            2,
+        );
+        assert!(index.is_fresh("a.test") && !index.is_fresh("b.test") && index.is_fresh("c.test"));
+        // Updating an existing entry doesn't evict others.
+        index.insert(
+            "a.test",
+            &lookup("a.test.", &["10.0.0.2"], Duration::from_secs(60)),
+            2,
+        );
+        assert!(index.is_fresh("a.test") && index.is_fresh("c.test"));
+
+        let now = Instant::now();
+        for i in 0..4 {
+            index.insert_negative(
+                &format!("missing-{i}.test"),
+                now + Duration::from_secs(i),
+                2,
+            );
+        }
+        assert_eq!(index.negatives.read().unwrap().len(), 2);
+        assert!(index.negative_ttl_remaining("missing-3.test").is_some());
+
+        index.insert(
+            "d.test",
+            &lookup("d.test.", &["10.0.0.1"], Duration::from_secs(60)),
+            0,
+        );
+        assert!(!index.is_fresh("d.test"));
+    }
+}
@@ -0,0 +1,159 @@
+use std::net::SocketAddr;
+#[cfg(feature = "toml")]
+use std::path::Path;
+use std::time::Duration;
+
+use hickory_resolver::config::LookupIpStrategy;
+use hickory_resolver::config::NameServerConfig;
+use hickory_resolver::config::NameServerConfigGroup;
+use hickory_resolver::config::ResolverConfig;
+use hickory_resolver::name_server::ConnectionProvider;
+use serde::Deserialize;
+use serde::Serialize;
+
+use crate::DnsProtocol;
+use crate::HickoryResolver;
+use crate::HickoryResolverBuilder;
+use crate::ResolveError;
+
+/// HickoryResolverConfig is the serializable configuration of [`HickoryResolver`], which
+/// could be loaded from config files like YAML or TOML.
+///
+/// Unknown fields are rejected so that typos will be caught.
+///
+/// ```
+/// use reqwest_hickory_resolver::HickoryResolverConfig;
+///
+/// let cfg: HickoryResolverConfig = serde_json::from_str(
+///     r#"{"nameservers": ["1.1.1.1:53"], "protocol": "tcp", "timeout": 1.5}"#,
+/// )
+/// .unwrap();
+/// assert_eq!(cfg.nameservers.len(), 1);
+/// ```
+#[derive(Debug, Default, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+#[non_exhaustive]
+pub struct HickoryResolverConfig {
+    /// The name servers to use, the system configuration will be used if empty.
+    pub nameservers: Vec<SocketAddr>,
+    /// The name servers with their own protocol, port and TLS name, which take precedence
+    /// over `nameservers` and `protocol`.
+    pub name_servers: Vec<NameServerConfig>,
+    /// The protocol used to talk with the name servers.
+    pub protocol: Option<DnsProtocol>,
+    /// Whether to shuffle the resolved ip addrs.
+    #[cfg(feature = "shuffle")]
+    pub shuffle: bool,
+    /// The timeout for the whole resolution of a name, in seconds.
+    #[serde(with = "duration_secs")]
+    pub timeout: Option<Duration>,
+    /// The strategy used to query ip addrs.
+    pub ip_strategy: Option<LookupIpStrategy>,
+}
+
+impl HickoryResolver {
+    /// Create a new resolver from the given [`HickoryResolverConfig`].
+    ///
+    /// The config goes through [`HickoryResolverBuilder::build`], so that the conflicting
+    /// fields like `name_servers` with `nameservers` or `protocol` will return an error.
+    ///
+    /// ```
+    /// use reqwest_hickory_resolver::HickoryResolver;
+    /// use reqwest_hickory_resolver::HickoryResolverConfig;
+    ///
+    /// let cfg: HickoryResolverConfig = serde_json::from_str(
+    ///     r#"{"name_servers": [{"socket_addr": "10.0.0.1:53", "protocol": "udp"}], "protocol": "tcp"}"#,
+    /// )
+    /// .unwrap();
+    /// assert!(HickoryResolver::from_config(cfg).is_err());
+    /// ```
+    pub fn from_config(cfg: HickoryResolverConfig) -> Result<Self, ResolveError> {
+        Self::builder().apply_config(cfg).build()
+    }
+
+    /// Create a new resolver from the [`HickoryResolverConfig`] in the given TOML file.
+    ///
+    /// ```
+    /// use reqwest_hickory_resolver::HickoryResolver;
+    ///
+    /// let path = std::env::temp_dir().join("reqwest-hickory-resolver-doctest.toml");
+    /// std::fs::write(
+    ///     &path,
+    ///     r#"
+    /// timeout = 3.0
+    ///
+    /// [[name_servers]]
+    /// socket_addr = "10.0.0.1:53"
+    /// protocol = "udp"
+    ///
+    /// [[name_servers]]
+    /// socket_addr = "1.1.1.1:853"
+    /// protocol = "tcp"
+    /// tls_dns_name = "cloudflare-dns.com"
+    /// "#,
+    /// )
+    /// .unwrap();
+    /// assert!(HickoryResolver::from_toml(&path).is_ok());
+    ///
+    /// std::fs::write(&path, "[[name_servers]]\nsocket_addr = \"not-an-addr\"\n").unwrap();
+    /// let err = HickoryResolver::from_toml(&path).unwrap_err();
+    /// assert!(err.to_string().contains("socket_addr"));
+    /// ```
+    #[cfg(feature = "toml")]
+    pub fn from_toml(path: &Path) -> Result<Self, ResolveError> {
+        let content = std::fs::read_to_string(path)?;
+        let cfg = toml::from_str(&content)
+            .map_err(|e| ResolveError::Config(format!("invalid config {}: {e}", path.display())))?;
+
+        Self::from_config(cfg)
+    }
+}
+
+impl<P: ConnectionProvider> HickoryResolverBuilder<P> {
+    /// Apply the given [`HickoryResolverConfig`] on this builder.
+    fn apply_config(mut self, cfg: HickoryResolverConfig) -> Self {
+        if !cfg.name_servers.is_empty() {
+            let group = NameServerConfigGroup::from(cfg.name_servers);
+            self = self.config(ResolverConfig::from_parts(None, vec![], group));
+        }
+        if !cfg.nameservers.is_empty() {
+            self = self.nameservers(cfg.nameservers);
+        }
+        if let Some(protocol) = cfg.protocol {
+            self = self.protocol(protocol);
+        }
+        if let Some(timeout) = cfg.timeout {
+            self = self.timeout(timeout);
+        }
+        if let Some(strategy) = cfg.ip_strategy {
+            self = self.ip_strategy(strategy);
+        }
+        #[cfg(feature = "shuffle")]
+        {
+            self = self.shuffle(cfg.shuffle);
+        }
+        self
+    }
+}
+
+/// Serialize the optional duration as seconds in float.
+mod duration_secs {
+    use std::time::Duration;
+
+    use serde::Deserialize;
+    use serde::Deserializer;
+    use serde::Serializer;
+
+    pub fn serialize<S: Serializer>(v: &Option<Duration>, s: S) -> Result<S::Ok, S::Error> {
+        match v {
+            Some(d) => s.serialize_some(&d.as_secs_f64()),
+            None => s.serialize_none(),
+        }
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(d: D) -> Result<Option<Duration>, D::Error> {
+        Option::<f64>::deserialize(d)?
+            .map(|secs| Duration::try_from_secs_f64(secs).map_err(serde::de::Error::custom))
+            .transpose()
+    }
+}
@@ -0,0 +1,121 @@
+use std::error::Error;
+use std::fmt;
+use std::io;
+
+use hickory_resolver::error::ResolveErrorKind;
+use hickory_resolver::proto::error::ProtoError;
+
+/// ResolveError is the error returned by [`HickoryResolver`](crate::HickoryResolver).
+#[derive(Debug)]
+#[non_exhaustive]
+pub enum ResolveError {
+    /// No records were found for the name.
+    NoRecords {
+        /// The name that has been resolved.
+        name: String,
+    },
+    /// The resolution timed out.
+    Timeout,
+    /// The name is refused to be resolved by the blocklist or allowlist.
+    Blocked {
+        /// The name that has been refused.
+        name: String,
+    },
+    /// All the resolved addrs are rejected as private or denied by the CIDRs.
+    Denied {
+        /// The name that has been resolved.
+        name: String,
+    },
+    /// The DNSSEC validation of the answers failed.
+    #[cfg(feature = "dnssec")]
+    Dnssec(ProtoError),
+    /// Fewer addrs than required are resolved, see
+    /// [`HickoryResolver::with_min_addrs`](crate::HickoryResolver::with_min_addrs).
+    InsufficientRecords {
+        /// The number of distinct addrs resolved.
+        found: usize,
+        /// The number of distinct addrs required.
+        required: usize,
+    },
+    /// The configuration of the resolver is invalid.
+    Config(String),
+    /// The resolver is used outside of a Tokio runtime.
+    NoRuntime,
+    /// An IO error occurred.
+    Io(io::Error),
+    /// A DNS protocol error occurred.
+    Proto(ProtoError),
+    /// Other errors returned by hickory.
+    Other(String),
+}
+
+impl fmt::Display for ResolveError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ResolveError::NoRecords { name } => write!(f, "no records found for {name}"),
+            ResolveError::Timeout => write!(f, "resolution timed out"),
+            ResolveError::Blocked { name } => write!(f, "resolving {name} is blocked"),
+            ResolveError::Denied { name } => write!(f, "all addrs of {name} are denied"),
+            #[cfg(feature = "dnssec")]
+            ResolveError::Dnssec(err) => write!(f, "dnssec validation failed: {err}"),
+            ResolveError::InsufficientRecords { found, required } => {
+                write!(f, "only {found} addrs resolved while {required} required")
+            }
+            ResolveError::Config(msg) => write!(f, "invalid config: {msg}"),
+            ResolveError::NoRuntime => write!(f, "no tokio runtime is present"),
+            ResolveError::Io(err) => write!(f, "io error: {err}"),
+            ResolveError::Proto(err) => write!(f, "proto error: {err}"),
+            ResolveError::Other(msg) => write!(f, "{msg}"),
+        }
+    }
+}
+
+impl Error for ResolveError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            ResolveError::Io(err) => Some(err),
+            ResolveError::Proto(err) => Some(err),
+            #[cfg(feature = "dnssec")]
+            ResolveError::Dnssec(err) => Some(err),
+            _ => None,
+        }
+    }
+}
+
+impl ResolveError {
+    /// Returns whether the lookup could be retried on this error, definitive errors like
+    /// [`ResolveError::NoRecords`] will not be retried.
+    pub(crate) fn is_retryable(&self) -> bool {
+        match self {
+            ResolveError::NoRecords { .. }
+            | ResolveError::Blocked { .. }
+            | ResolveError::Denied { .. }
+            | ResolveError::InsufficientRecords { .. }
+            | ResolveError::Config(_)
+            | ResolveError::NoRuntime => false,
+            #[cfg(feature = "dnssec")]
+            ResolveError::Dnssec(_) => false,
+            _ => true,
+        }
+    }
+}
+
+impl From<hickory_resolver::error::ResolveError> for ResolveError {
+    fn from(err: hickory_resolver::error::ResolveError) -> Self {
+        match err.kind() {
+            ResolveErrorKind::NoRecordsFound { query, .. } => ResolveError::NoRecords {
+                name: query.name().to_utf8(),
+            },
+            ResolveErrorKind::Timeout => ResolveError::Timeout,
+            ResolveErrorKind::Io(io) => ResolveError::Io(io::Error::new(io.kind(), io.to_string())),
+            ResolveErrorKind::Proto(proto) => ResolveError::Proto(proto.clone()),
+            _ => ResolveError::Other(err.to_string()),
+        }
+    }
+}
+
+impl From<io::Error> for ResolveError {
+    fn from(err: io::Error) -> Self {
+        ResolveError::Io(err)
+    }
+}
@@ -6,14 +6,12 @@
 //! Init client with `HickoryResolver`.
 //!
 //! ```
-//! use std::sync::Arc;
-//!
 //! use reqwest::ClientBuilder;
 //! use reqwest_hickory_resolver::HickoryResolver;
 //!
 //! fn init_with_hickory_resolver() -> reqwest::Result<()> {
 //!     let mut builder = ClientBuilder::new();
-//!     builder = builder.dns_resolver(Arc::new(HickoryResolver::default()));
+//!     builder = builder.dns_resolver(HickoryResolver::default().shared());
 //!     builder.build()?;
 //!     Ok(())
 //! }
@@ -30,7 +28,7 @@
 //! use reqwest_hickory_resolver::HickoryResolver;
 //!
 //! static GLOBAL_RESOLVER: Lazy<Arc<HickoryResolver>> =
-//!     Lazy::new(|| Arc::new(HickoryResolver::default()));
+//!     Lazy::new(|| HickoryResolver::default().shared());
 //!
 //! fn init_with_hickory_resolver() -> reqwest::Result<()> {
 //!     let mut builder = ClientBuilder::new();
@@ -40,75 +38,3003 @@
 //! }
 //! ```
 
-use std::io;
+use std::borrow::Cow;
+use std::collections::HashMap;
+use std::collections::HashSet;
+use std::fmt;
+use std::hash::DefaultHasher;
+use std::hash::Hash;
+use std::hash::Hasher;
+use std::net::IpAddr;
+use std::net::Ipv4Addr;
+use std::net::Ipv6Addr;
 use std::net::SocketAddr;
+use std::net::SocketAddrV6;
+use std::path::Path;
+use std::path::PathBuf;
+use std::sync::atomic::AtomicUsize;
+use std::sync::atomic::Ordering;
 use std::sync::Arc;
+use std::sync::Mutex;
+use std::sync::RwLock;
+use std::time::Duration;
+use std::time::Instant;
 
+use hickory_resolver::config::LookupIpStrategy;
+use hickory_resolver::config::NameServerConfig;
+use hickory_resolver::config::NameServerConfigGroup;
+use hickory_resolver::config::Protocol;
+use hickory_resolver::config::ResolverConfig;
+use hickory_resolver::config::ResolverOpts;
+use hickory_resolver::config::CLOUDFLARE_IPS;
+use hickory_resolver::lookup_ip::LookupIp;
+use hickory_resolver::name_server::ConnectionProvider;
+use hickory_resolver::name_server::TokioConnectionProvider;
+use hickory_resolver::proto::rr::rdata::opt::ClientSubnet;
 use hickory_resolver::system_conf;
-use hickory_resolver::TokioAsyncResolver;
-use once_cell::sync::OnceCell;
+use hickory_resolver::AsyncResolver;
+use ipnet::IpNet;
 use reqwest::dns::Addrs;
 use reqwest::dns::Name;
 use reqwest::dns::Resolve;
 use reqwest::dns::Resolving;
+use tokio::sync::Semaphore;
+
+mod builder;
+pub use builder::HickoryResolverBuilder;
+mod cache;
+use cache::CacheIndex;
+pub use cache::CacheStats;
+pub use cache::DnsCache;
+mod error;
+pub use error::ResolveError;
+mod provider;
+pub use provider::InterceptingConn;
+pub use provider::InterceptingProvider;
+use provider::NameserverStats;
+mod race;
+pub use race::RaceResolver;
+
+#[cfg(feature = "blocking")]
+mod blocking;
+#[cfg(feature = "blocking")]
+pub use blocking::BlockingHickoryResolver;
+
+#[cfg(feature = "serde")]
+mod config;
+
+#[cfg(test)]
+mod tests;
+#[cfg(feature = "serde")]
+pub use config::HickoryResolverConfig;
+
+pub use hickory_resolver::lookup::Lookup;
+pub use hickory_resolver::proto::rr::RecordType;
+
+/// The messages of the DNSSEC validation failures reported by hickory.
+#[cfg(feature = "dnssec")]
+const DNSSEC_FAILURES: &[&str] = &[
+    "exceeded max validation depth",
+    "could not validate negative response missing SOA",
+    "could not validate negative response with NSEC",
+    "no results to verify",
+    "Could not validate all DNSKEYs",
+    "validation failed",
+    "revoked",
+    "is not a zone key",
+    "mismatched algorithm",
+];
+
+/// The default backoff between retries of a failed lookup.
+const RETRY_BACKOFF: Duration = Duration::from_millis(100);
+
+/// The well-known name used by [`HickoryResolver::healthcheck`] if no probe is specified.
+pub const DEFAULT_HEALTHCHECK_PROBE: &str = "example.com";
+
+/// The environment variable to read `resolv.conf` from a non-standard path, see
+/// [`HickoryResolver::with_resolv_conf_path`].
+#[cfg(unix)]
+pub const RESOLV_CONF_ENV: &str = "HICKORY_RESOLV_CONF";
 
 /// HickoryResolver implements reqwest [`Resolve`] so that we can use it as reqwest's DNS resolver.
-#[derive(Debug, Default, Clone)]
-pub struct HickoryResolver {
+///
+/// The resolver is generic over the [`ConnectionProvider`] so that DNS could be talked in
+/// custom ways, it defaults to [`TokioConnectionProvider`].
+#[derive(Clone)]
+pub struct HickoryResolver<P: ConnectionProvider = TokioConnectionProvider> {
     /// Since we might not have been called in the context of a
     /// Tokio Runtime in initialization, so we must delay the actual
     /// construction of the resolver.
-    state: Arc<OnceCell<TokioAsyncResolver>>,
-    rng: Option<rand::rngs::SmallRng>,
+    ///
+    /// The resolver is swapped on reload, in-flight lookups keep using the old one.
+    state: Arc<RwLock<Option<State<P>>>>,
+    provider: P,
+    options: Arc<Options>,
+    fallback: Option<Arc<HickoryResolver<P>>>,
+    /// The resolvers routed by the lowercased name suffix.
+    routes: Arc<Vec<(String, Arc<HickoryResolver<P>>)>>,
+    cache_index: Arc<CacheIndex>,
+}
+
+impl Default for HickoryResolver {
+    fn default() -> Self {
+        Self::from_provider(TokioConnectionProvider::default())
+    }
+}
+
+impl<P: ConnectionProvider> fmt::Debug for HickoryResolver<P> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("HickoryResolver")
+            .field("state", &self.state)
+            .field("options", &self.options)
+            .field("fallback", &self.fallback)
+            .field("routes", &self.routes)
+            .finish_non_exhaustive()
+    }
+}
+
+/// SlowQueryHook is the hook invoked when a resolution is slow, see
+/// [`HickoryResolver::with_slow_query_threshold`].
+pub type SlowQueryHook = Arc<dyn Fn(&Name, Duration) + Send + Sync>;
+
+/// InnerResolver is the underlying hickory resolver of [`HickoryResolver`].
+type InnerResolver<P> = AsyncResolver<InterceptingProvider<P>>;
+
+/// State is the initialized underlying resolver along with the name servers it uses.
+#[derive(Clone)]
+struct State<P: ConnectionProvider> {
+    resolver: Arc<InnerResolver<P>>,
+    /// The resolver with the same configuration but without cache, which is used to
+    /// lookup the names fresh.
+    fresh: Arc<InnerResolver<P>>,
+    nameservers: Vec<SocketAddr>,
+}
+
+impl<P: ConnectionProvider> State<P> {
+    fn resolver(&self, fresh: bool) -> Arc<InnerResolver<P>> {
+        if fresh {
+            self.fresh.clone()
+        } else {
+            self.resolver.clone()
+        }
+    }
+}
+
+impl<P: ConnectionProvider> fmt::Debug for State<P> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("State")
+            .field("resolver", &self.resolver)
+            .field("fresh", &self.fresh)
+            .field("nameservers", &self.nameservers)
+            .finish()
+    }
+}
+
+/// NameRewrite is the function to rewrite the names before resolving, see
+/// [`HickoryResolver::with_name_rewrite`].
+pub type NameRewrite = Arc<dyn Fn(&str) -> String + Send + Sync>;
+
+/// ResolveHook is the hook invoked after a name is resolved, see
+/// [`HickoryResolver::with_on_resolve`].
+pub type ResolveHook = Arc<dyn Fn(&Name, &[SocketAddr]) + Send + Sync>;
+
+/// Opaque wraps the values that don't implement `Debug` like hooks.
+#[derive(Clone)]
+struct Opaque<T>(T);
+
+impl<T> fmt::Debug for Opaque<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("Opaque")
+    }
+}
+
+/// BoxedRng is the rng used to shuffle the ip addrs, see [`HickoryResolver::with_rng`].
+#[cfg(feature = "shuffle")]
+pub type BoxedRng = Box<dyn rand::RngCore + Send + Sync>;
+
+/// Options holds the options of [`HickoryResolver`] set by users.
+#[derive(Debug, Default, Clone)]
+struct Options {
+    config: Option<ResolverConfig>,
+    fallback_config: Option<ResolverConfig>,
+    opts: Option<ResolverOpts>,
+    ip_strategy: Option<LookupIpStrategy>,
+    attempts: Option<usize>,
+    cache_size: Option<usize>,
+    rotate_nameservers: Option<bool>,
+    preserve_intermediates: Option<bool>,
+    negative_caching: Option<bool>,
+    ttl_bounds: Option<(Duration, Duration)>,
+    ttl_overrides: HashMap<String, Duration>,
+    #[cfg(feature = "dnssec")]
+    dnssec: Option<bool>,
+    client_subnet: Option<ClientSubnet>,
+    search_domains: Option<Vec<String>>,
+    ndots: Option<usize>,
+    #[cfg(unix)]
+    resolv_conf_path: Option<PathBuf>,
+    #[cfg(unix)]
+    disable_default_fallback: bool,
+    nameservers: Option<Vec<SocketAddr>>,
+    protocol: Option<DnsProtocol>,
+    transport: Option<Transport>,
+    tls_dns_name: Option<String>,
+    bootstrap_nameservers: Option<Vec<IpAddr>>,
+    family_preference: FamilyPreference,
+    timeout: Option<Duration>,
+    connect_timeout: Option<Duration>,
+    bind_addr: Option<IpAddr>,
+    case_randomization: bool,
+    nameserver_stats: Arc<NameserverStats>,
+    retries: usize,
+    retry_backoff: Backoff,
+    /// The semaphore used to bound the concurrent lookups.
+    concurrency: Option<Arc<Semaphore>>,
+    static_hosts: HashMap<String, Vec<IpAddr>>,
+    /// Whether only the static hosts are resolved, see [`HickoryResolver::mock`].
+    #[cfg(feature = "testing")]
+    mock: bool,
+    ipv6_scopes: HashMap<Ipv6Addr, u32>,
+    blocklist: HashSet<String>,
+    allowlist: Option<HashSet<String>>,
+    /// The counter used to rotate the ip addrs, rotation is enabled if it's set.
+    rotation: Option<Arc<AtomicUsize>>,
+    /// The preferred addrs of each name, sticky is enabled if it's set.
+    sticky: Option<Arc<StickyIndex>>,
+    /// The observed latency of each addr, latency feedback is enabled if it's set.
+    latency: Option<Arc<LatencyIndex>>,
+    sorted: bool,
+    reject_private: bool,
+    denied_cidrs: Vec<IpNet>,
+    interleave: bool,
+    preserve_order: bool,
+    max_addrs: Option<usize>,
+    min_addrs: Option<usize>,
+    /// Whether the duplicated addrs are kept, see [`HickoryResolver::with_dedup`].
+    keep_duplicates: bool,
+    prefetch_refresh: Option<f32>,
+    /// The max number of IPv4 and IPv6 addrs.
+    max_addrs_per_family: Option<(usize, usize)>,
+    name_rewrite: Option<Opaque<NameRewrite>>,
+    on_resolve: Option<Opaque<ResolveHook>>,
+    slow_query: Option<(Duration, Opaque<SlowQueryHook>)>,
+    cache: Option<Opaque<Arc<dyn DnsCache>>>,
+    /// The rng used to shuffle the ip addrs, shuffle is enabled if it's set.
+    #[cfg(feature = "shuffle")]
+    rng: Option<Opaque<Arc<Mutex<BoxedRng>>>>,
 }
 
 impl HickoryResolver {
-    /// Enable shuffle for the hickory resolver to make sure the ip addrs returned are shuffled.
+    /// Create a new resolver from the system configuration.
     ///
-    /// NOTES: introduce shuffle will add extra overhead like more allocations and shuffling.
-    pub fn with_shuffle(mut self, shuffle: bool) -> Self {
-        if shuffle {
-            use rand::SeedableRng;
-            self.rng = Some(rand::rngs::SmallRng::from_entropy());
+    /// Unlike [`HickoryResolver::default`], the system configuration is read eagerly so
+    /// that a broken DNS setup is reported here instead of on the first request. The
+    /// actual resolver is still constructed lazily.
+    ///
+    /// The `resolv.conf` at the path of [`RESOLV_CONF_ENV`] will be read instead if set.
+    pub fn from_system_conf() -> Result<Self, ResolveError> {
+        #[cfg(unix)]
+        if let Some(path) = std::env::var_os(RESOLV_CONF_ENV) {
+            let (config, opts) = read_resolv_conf(Path::new(&path))?;
+            return Ok(Self::default().with_config(config).with_options(opts));
         }
+        let (config, opts) = system_conf::read_system_conf()?;
 
-        self
+        Ok(Self::default().with_config(config).with_options(opts))
+    }
+
+    /// Create a new resolver with caching fully disabled, so that every resolution goes
+    /// to the wire.
+    ///
+    /// This is the same as setting [`HickoryResolver::with_cache_size`] to `0`, useful when
+    /// stale addrs are dangerous at the cost of latency.
+    pub fn uncached() -> Self {
+        Self::default().with_cache_size(0)
+    }
+
+    /// Create a mock resolver resolving the names purely from the given map, which never
+    /// touches the network so that it could be used as a deterministic test double.
+    ///
+    /// Unknown names fail with [`ResolveError::NoRecords`], while the other options like
+    /// shuffle still apply. The lookups of other records like
+    /// [`HickoryResolver::lookup_txt`] always fail.
+    ///
+    /// ```
+    /// use std::collections::HashMap;
+    ///
+    /// use reqwest_hickory_resolver::HickoryResolver;
+    /// use reqwest_hickory_resolver::ResolveError;
+    ///
+    /// let resolver = HickoryResolver::mock(HashMap::from([(
+    ///     "api.example.com".to_string(),
+    ///     vec!["192.0.2.1".parse().unwrap()],
+    /// )]));
+    ///
+    /// # tokio::runtime::Builder::new_current_thread().enable_all().build().unwrap().block_on(async {
+    /// let addrs = resolver.resolve_with_port("api.example.com", 443).await.unwrap();
+    /// assert_eq!(addrs, vec!["192.0.2.1:443".parse().unwrap()]);
+    /// let err = resolver.resolve_with_port("unknown.example.com", 443).await;
+    /// assert!(matches!(err, Err(ResolveError::NoRecords { .. })));
+    /// # });
+    /// ```
+    #[cfg(feature = "testing")]
+    pub fn mock(map: HashMap<String, Vec<IpAddr>>) -> Self {
+        let mut resolver = map
+            .into_iter()
+            .fold(Self::default(), |resolver, (name, addrs)| {
+                resolver.with_static_host(&name, addrs)
+            });
+        resolver.options_mut().mock = true;
+        resolver
+    }
+
+    /// Create a new resolver from the given `resolv.conf` contents without touching the
+    /// filesystem.
+    ///
+    /// ```
+    /// use reqwest_hickory_resolver::HickoryResolver;
+    ///
+    /// let resolver = HickoryResolver::from_resolv_conf_str(
+    ///     "nameserver 10.0.0.1\nsearch svc.cluster.local\noptions ndots:5\n",
+    /// );
+    /// assert!(resolver.is_ok());
+    /// assert!(HickoryResolver::from_resolv_conf_str("nameserver not-an-ip").is_err());
+    /// ```
+    #[cfg(unix)]
+    pub fn from_resolv_conf_str(contents: &str) -> Result<Self, ResolveError> {
+        let (config, opts) = system_conf::parse_resolv_conf(contents)
+            .map_err(|e| ResolveError::Config(format!("invalid resolv.conf: {e}")))?;
+
+        Ok(Self::default().with_config(config).with_options(opts))
     }
 }
 
-impl Resolve for HickoryResolver {
-    fn resolve(&self, name: Name) -> Resolving {
-        let mut hickory_resolver = self.clone();
-        Box::pin(async move {
-            let resolver = hickory_resolver.state.get_or_try_init(new_resolver)?;
+impl<P: ConnectionProvider> HickoryResolver<P> {
+    /// Create a new resolver with the given [`ConnectionProvider`].
+    ///
+    /// This is useful to route DNS through custom transports or to inject a fake provider
+    /// in tests.
+    pub fn from_provider(provider: P) -> Self {
+        HickoryResolver {
+            state: Arc::default(),
+            provider,
+            options: Arc::default(),
+            fallback: None,
+            routes: Arc::default(),
+            cache_index: Arc::default(),
+        }
+    }
 
-            let lookup = resolver.lookup_ip(name.as_str()).await?;
+    /// Wrap this resolver in an [`Arc`] so that it could be passed to
+    /// [`reqwest::ClientBuilder::dns_resolver`] directly.
+    pub fn shared(self) -> Arc<Self> {
+        Arc::new(self)
+    }
 
-            let addrs: Addrs = if let Some(rng) = &mut hickory_resolver.rng {
-                use rand::seq::SliceRandom;
+    /// Use the given [`ResolverConfig`] instead of reading the system configuration.
+    ///
+    /// This is useful for applications that ship their own DNS settings and must not
+    /// depend on `/etc/resolv.conf`.
+    pub fn with_config(mut self, config: ResolverConfig) -> Self {
+        self.options_mut().config = Some(config);
+        self
+    }
 
-                // Collect all the addresses into a vector and shuffle them.
-                let mut ips = lookup.into_iter().collect::<Vec<_>>();
-                ips.shuffle(rng);
+    /// Use the given [`ResolverConfig`] if the system configuration can't be read, for
+    /// example to point at the internal name servers instead of failing the resolutions.
+    ///
+    /// This is not used if the system configuration is replaced by other options like
+    /// [`HickoryResolver::with_nameservers`].
+    pub fn with_fallback_config(mut self, config: ResolverConfig) -> Self {
+        self.options_mut().fallback_config = Some(config);
+        self
+    }
 
-                Box::new(ips.into_iter().map(|addr| SocketAddr::new(addr, 0)))
-            } else {
-                Box::new(lookup.into_iter().map(|addr| SocketAddr::new(addr, 0)))
+    /// Use the given [`ResolverOpts`] instead of the ones from the system configuration.
+    pub fn with_options(mut self, opts: ResolverOpts) -> Self {
+        self.options_mut().opts = Some(opts);
+        self
+    }
+
+    /// Set the [`LookupIpStrategy`] used to query ip addrs, for example `Ipv4Only` for
+    /// IPv4-only networks.
+    ///
+    /// This will be merged with the options set by [`HickoryResolver::with_options`].
+    pub fn with_ip_strategy(mut self, strategy: LookupIpStrategy) -> Self {
+        self.options_mut().ip_strategy = Some(strategy);
+        self
+    }
+
+    /// Set the number of attempts hickory makes for each query before giving up.
+    ///
+    /// This is independent of [`HickoryResolver::with_retries`] which retries the whole
+    /// lookup. This will be merged with the options set by [`HickoryResolver::with_options`].
+    pub fn with_attempts(mut self, attempts: usize) -> Self {
+        self.options_mut().attempts = Some(attempts);
+        self
+    }
+
+    /// Spread the queries across the configured name servers instead of always preferring
+    /// the best ranked one, so that the secondaries are utilized and their failures surface.
+    ///
+    /// The name servers are picked randomly for each query, `num_concurrent_reqs` of
+    /// [`ResolverOpts`] of them are queried at once. This will be merged with the options
+    /// set by [`HickoryResolver::with_options`].
+    pub fn with_rotate_nameservers(mut self, enabled: bool) -> Self {
+        self.options_mut().rotate_nameservers = Some(enabled);
+        self
+    }
+
+    /// Set the max number of records cached by the underlying resolver.
+    ///
+    /// This will be merged with the options set by [`HickoryResolver::with_options`].
+    pub fn with_cache_size(mut self, size: usize) -> Self {
+        self.options_mut().cache_size = Some(size);
+        self
+    }
+
+    /// Preserve the intermediate records like CNAME in the lookup response.
+    ///
+    /// This will be merged with the options set by [`HickoryResolver::with_options`].
+    pub fn with_preserve_intermediates(mut self, enabled: bool) -> Self {
+        self.options_mut().preserve_intermediates = Some(enabled);
+        self
+    }
+
+    /// Enable or disable the caching of negative responses like `NXDOMAIN`.
+    ///
+    /// When disabled, a freshly-created record will be picked up on the next lookup instead
+    /// of waiting for the negative TTL to expire. This will be merged with the options set
+    /// by [`HickoryResolver::with_options`].
+    pub fn with_negative_caching(mut self, enabled: bool) -> Self {
+        self.options_mut().negative_caching = Some(enabled);
+        self
+    }
+
+    /// Clamp the TTL of both positive and negative responses into `[min, max]` regardless
+    /// of what the name servers claim.
+    ///
+    /// This will be merged with the options set by [`HickoryResolver::with_options`], and
+    /// disabled negative caching takes precedence over it.
+    pub fn with_ttl_bounds(mut self, min: Duration, max: Duration) -> Self {
+        self.options_mut().ttl_bounds = Some((min, max));
+        self
+    }
+
+    /// Enable or disable DNSSEC validation of the answers.
+    ///
+    /// A validation failure will be reported as [`ResolveError::Dnssec`]. This will be merged
+    /// with the options set by [`HickoryResolver::with_options`].
+    #[cfg(feature = "dnssec")]
+    pub fn with_dnssec(mut self, enabled: bool) -> Self {
+        self.options_mut().dnssec = Some(enabled);
+        self
+    }
+
+    /// Cache the addrs of the given name for `ttl` instead of the TTL from the name servers.
+    ///
+    /// The name is matched case-insensitively, names with override are always looked up
+    /// bypassing the cache of the underlying resolver once expired.
+    pub fn with_ttl_override(mut self, name: &str, ttl: Duration) -> Self {
+        let name = name.trim_end_matches('.').to_ascii_lowercase();
+        self.options_mut().ttl_overrides.insert(name, ttl);
+        self
+    }
+
+    /// Attach the EDNS Client Subnet to the outgoing queries so that geo-targeted answers
+    /// reflect the given subnet, both IPv4 and IPv6 prefixes are supported.
+    pub fn with_client_subnet(mut self, subnet: IpNet) -> Self {
+        self.options_mut().client_subnet = Some(subnet.trunc().into());
+        self
+    }
+
+    /// Set the search domains that will be appended in order to names with fewer dots
+    /// than ndots, for example `svc.cluster.local` in Kubernetes.
+    ///
+    /// The search domains replace the ones from the configuration.
+    pub fn with_search_domains(mut self, domains: Vec<String>) -> Self {
+        self.options_mut().search_domains = Some(domains);
+        self
+    }
+
+    /// Set the number of dots a name must have before it's tried as an absolute name
+    /// first, see `ndots` in `resolv.conf(5)`.
+    ///
+    /// This will be merged with the options set by [`HickoryResolver::with_options`].
+    pub fn with_ndots(mut self, ndots: usize) -> Self {
+        self.options_mut().ndots = Some(ndots);
+        self
+    }
+
+    /// Read the system configuration from the `resolv.conf` at the given path instead of
+    /// `/etc/resolv.conf`, for example a custom one mounted in containers.
+    ///
+    /// This takes precedence over [`RESOLV_CONF_ENV`]. If the file can't be read or parsed,
+    /// the system configuration will be used instead unless
+    /// [`HickoryResolver::with_disable_default_fallback`] is set.
+    #[cfg(unix)]
+    pub fn with_resolv_conf_path(mut self, path: PathBuf) -> Self {
+        self.options_mut().resolv_conf_path = Some(path);
+        self
+    }
+
+    /// Fail the resolution if the `resolv.conf` set by
+    /// [`HickoryResolver::with_resolv_conf_path`] or [`RESOLV_CONF_ENV`] can't be read,
+    /// instead of falling back to the system configuration.
+    ///
+    /// ```
+    /// use reqwest_hickory_resolver::HickoryResolver;
+    ///
+    /// let resolver = HickoryResolver::default()
+    ///     .with_resolv_conf_path("/nonexistent/resolv.conf".into())
+    ///     .with_disable_default_fallback(true);
+    ///
+    /// # tokio::runtime::Builder::new_current_thread().enable_all().build().unwrap().block_on(async {
+    /// assert!(resolver.init().await.is_err());
+    /// # });
+    /// ```
+    ///
+    /// NOTES: the configuration is never silently replaced by public name servers, a
+    /// failure to read the system configuration is returned as an error unless
+    /// [`HickoryResolver::with_fallback_config`] is set.
+    #[cfg(unix)]
+    pub fn with_disable_default_fallback(mut self, enabled: bool) -> Self {
+        self.options_mut().disable_default_fallback = enabled;
+        self
+    }
+
+    /// Use the given name servers instead of reading the system configuration.
+    ///
+    /// The name servers will be queried over UDP unless another protocol is set by
+    /// [`HickoryResolver::with_protocol`], the truncated answers are retried over TCP.
+    pub fn with_nameservers(mut self, servers: Vec<SocketAddr>) -> Self {
+        self.options_mut().nameservers = Some(servers);
+        self
+    }
+
+    /// Use the name servers parsed from a comma-separated string like `8.8.8.8,1.1.1.1:53`.
+    ///
+    /// Each entry could be either an ip or a socket addr, port 53 will be used if only
+    /// ip is given.
+    pub fn with_nameservers_str(self, s: &str) -> Result<Self, ResolveError> {
+        let servers = s
+            .split(',')
+            .map(str::trim)
+            .filter(|token| !token.is_empty())
+            .map(|token| {
+                token
+                    .parse::<SocketAddr>()
+                    .or_else(|_| token.parse::<IpAddr>().map(|ip| SocketAddr::new(ip, 53)))
+                    .map_err(|_| ResolveError::Config(format!("invalid nameserver: `{token}`")))
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+
+        Ok(self.with_nameservers(servers))
+    }
+
+    /// Set the [`DnsProtocol`] used to talk with the name servers.
+    ///
+    /// The well-known Cloudflare name servers will be used if no name servers are set by
+    /// [`HickoryResolver::with_nameservers`]. This doesn't take effect if a config is
+    /// supplied by [`HickoryResolver::with_config`].
+    pub fn with_protocol(mut self, protocol: DnsProtocol) -> Self {
+        self.options_mut().protocol = Some(protocol);
+        self
+    }
+
+    /// Set the [`Transport`] used to talk with all the plain name servers, for example TCP for
+    /// networks that block UDP.
+    ///
+    /// Unlike [`HickoryResolver::with_protocol`], this also applies to the name servers from
+    /// the system configuration, and the encrypted name servers are kept as is.
+    pub fn with_transport(mut self, transport: Transport) -> Self {
+        self.options_mut().transport = Some(transport);
+        self
+    }
+
+    /// Set the TLS server name used to verify the encrypted name servers.
+    ///
+    /// This only takes effect for encrypted protocols like [`DnsProtocol::Tls`].
+    pub fn with_tls_dns_name(mut self, name: &str) -> Self {
+        self.options_mut().tls_dns_name = Some(name.to_string());
+        self
+    }
+
+    /// Use the given name servers to resolve the hostname of the encrypted name server.
+    ///
+    /// This only takes effect if the encrypted name server is referenced by hostname via
+    /// [`HickoryResolver::with_tls_dns_name`] without explicit addrs, the bootstrap lookup
+    /// is done over UDP once before the resolver is constructed.
+    pub fn with_bootstrap_nameservers(mut self, servers: Vec<IpAddr>) -> Self {
+        self.options_mut().bootstrap_nameservers = Some(servers);
+        self
+    }
+
+    /// Set the [`FamilyPreference`] to make sure addrs of the preferred family are always
+    /// returned first.
+    ///
+    /// The relative order within each family is preserved, and shuffle (if enabled) happens
+    /// within each family instead of across the whole list.
+    pub fn with_family_preference(mut self, pref: FamilyPreference) -> Self {
+        self.options_mut().family_preference = pref;
+        self
+    }
+
+    /// Set the timeout for the whole resolution of a name.
+    ///
+    /// This is independent of the per-query timeout of hickory, so that the total latency
+    /// is bounded regardless of the internal retries.
+    pub fn with_timeout(mut self, timeout: Duration) -> Self {
+        self.options_mut().timeout = Some(timeout);
+        self
+    }
+
+    /// Set the timeout for establishing the connection to a name server, so that slow TCP
+    /// or TLS handshakes fail fast while the query itself could take longer.
+    ///
+    /// The query timeout is still the one from [`ResolverOpts`].
+    pub fn with_connect_timeout(mut self, timeout: Duration) -> Self {
+        self.options_mut().connect_timeout = Some(timeout);
+        self
+    }
+
+    /// Send the queries from the given local addr, so that the DNS traffic leaves from a
+    /// specific interface on multi-homed hosts.
+    ///
+    /// The addr only applies to the name servers of the same family, the port is picked by
+    /// the system. The providers other than hickory's generic one are given the addr by
+    /// `bind_addr` of [`NameServerConfig`].
+    pub fn with_bind_addr(mut self, addr: IpAddr) -> Self {
+        self.options_mut().bind_addr = Some(addr);
+        self
+    }
+
+    /// Enable DNS 0x20 to randomize the case of the query names for anti-spoofing, the
+    /// responses that don't echo the exact case will be rejected.
+    ///
+    /// Names are still compared case-insensitively everywhere else, so the results are
+    /// the same as without it:
+    ///
+    /// ```
+    /// use reqwest_hickory_resolver::HickoryResolver;
+    ///
+    /// let resolver = HickoryResolver::default()
+    ///     .with_case_randomization(true)
+    ///     .with_static_host("Api.Example.COM", vec!["192.0.2.1".parse().unwrap()]);
+    ///
+    /// # tokio::runtime::Builder::new_current_thread().enable_all().build().unwrap().block_on(async {
+    /// let addrs = resolver.resolve_with_port("api.example.com", 443).await.unwrap();
+    /// assert_eq!(addrs, vec!["192.0.2.1:443".parse().unwrap()]);
+    /// # });
+    /// ```
+    ///
+    /// NOTES: some name servers don't preserve the case of the questions, don't enable this
+    /// for them.
+    pub fn with_case_randomization(mut self, enabled: bool) -> Self {
+        self.options_mut().case_randomization = enabled;
+        self
+    }
+
+    /// Retry the lookup up to `attempts` times on error before giving up.
+    ///
+    /// The lookup will be retried after a short fixed backoff unless another one is set by
+    /// [`HickoryResolver::with_retry_backoff`], definitive answers like `NoRecordsFound` will
+    /// not be retried.
+    pub fn with_retries(mut self, attempts: usize) -> Self {
+        self.options_mut().retries = attempts;
+        self
+    }
+
+    /// Set the [`Backoff`] between retries, for example an exponential one to be gentle on
+    /// a struggling upstream.
+    pub fn with_retry_backoff(mut self, strategy: Backoff) -> Self {
+        self.options_mut().retry_backoff = strategy;
+        self
+    }
+
+    /// Limit the number of concurrent lookups to the name servers, the lookups beyond the
+    /// limit will wait instead of failing.
+    ///
+    /// The limit is shared by all the clones of this resolver. A limit of 0 would make the
+    /// lookups wait forever, so they fail with [`ResolveError::Config`] right away instead,
+    /// and [`HickoryResolverBuilder::build`] will return an error for it.
+    pub fn with_max_concurrency(mut self, n: usize) -> Self {
+        let semaphore = Semaphore::new(n);
+        // No permit will ever be granted, closing it fails the lookups at once.
+        if n == 0 {
+            semaphore.close();
+        }
+        self.options_mut().concurrency = Some(Arc::new(semaphore));
+        self
+    }
+
+    /// Delegate to the given resolver if we failed to resolve a name.
+    ///
+    /// The fallback resolver could have its own fallback, so that the resolvers will be
+    /// tried in order. The names and addrs refused by our policies, failing with
+    /// [`ResolveError::Blocked`] or [`ResolveError::Denied`], are never delegated.
+    pub fn with_fallback(mut self, fallback: Arc<HickoryResolver<P>>) -> Self {
+        self.fallback = Some(fallback);
+        self
+    }
+
+    /// Route the names matching the given suffix to the given resolver, for example
+    /// `internal` to resolve `*.internal` via the corporate name servers.
+    ///
+    /// The suffix is matched case-insensitively against the whole labels, and the longest
+    /// matching suffix wins. Names not matching any suffix are resolved by this resolver.
+    ///
+    /// The routed names are still checked against the blocklist, allowlist and min addrs of
+    /// this resolver, the routed addrs are filtered by its private and CIDR rejection, and
+    /// they go through its fallback and hooks as well.
+    ///
+    /// ```
+    /// use reqwest::dns::Resolve;
+    /// use reqwest_hickory_resolver::HickoryResolver;
+    ///
+    /// let internal = HickoryResolver::default()
+    ///     .with_static_host("db.corp.internal", vec!["10.0.0.1".parse().unwrap()])
+    ///     .shared();
+    /// let resolver = HickoryResolver::default()
+    ///     .with_static_host("db.corp.internal", vec!["192.0.2.1".parse().unwrap()])
+    ///     .with_routed_resolver("internal", internal);
+    ///
+    /// let name = "db.corp.internal".parse().unwrap();
+    /// let addrs = futures::executor::block_on(resolver.resolve(name)).unwrap();
+    /// let addrs = addrs.collect::<Vec<_>>();
+    /// assert_eq!(addrs, vec!["10.0.0.1:0".parse().unwrap()]);
+    /// ```
+    pub fn with_routed_resolver(mut self, suffix: &str, resolver: Arc<HickoryResolver<P>>) -> Self {
+        let suffix = suffix.trim_matches('.').to_ascii_lowercase();
+        let routes = Arc::make_mut(&mut self.routes);
+        routes.retain(|(v, _)| *v != suffix);
+        routes.push((suffix, resolver));
+        // Keep the longest suffix first so that the first match wins.
+        routes.sort_by_key(|(suffix, _)| std::cmp::Reverse(suffix.len()));
+        self
+    }
+
+    /// Add a static mapping for the given name which bypasses the DNS lookup.
+    ///
+    /// The name is matched case-insensitively ignoring the trailing dot, and the addrs will
+    /// still be arranged by family preference and shuffle.
+    pub fn with_static_host(mut self, name: &str, addrs: Vec<IpAddr>) -> Self {
+        self.options_mut()
+            .static_hosts
+            .insert(name.trim_end_matches('.').to_ascii_lowercase(), addrs);
+        self
+    }
+
+    /// Attach the given scope id to the IPv6 addr whenever it's resolved, so that link-local
+    /// targets like `fe80::1%eth0` are reachable.
+    ///
+    /// This applies to both static hosts and the addrs from the lookup.
+    ///
+    /// ```
+    /// use std::net::SocketAddr;
+    ///
+    /// use reqwest::dns::Resolve;
+    /// use reqwest_hickory_resolver::HickoryResolver;
+    ///
+    /// let ip = "fe80::1".parse().unwrap();
+    /// let resolver = HickoryResolver::default()
+    ///     .with_static_host("printer.local", vec![std::net::IpAddr::V6(ip)])
+    ///     .with_ipv6_scope(ip, 2);
+    ///
+    /// let name = "printer.local".parse().unwrap();
+    /// let addrs = futures::executor::block_on(resolver.resolve(name)).unwrap();
+    /// let addrs = addrs.collect::<Vec<_>>();
+    /// assert!(matches!(addrs[..], [SocketAddr::V6(addr)] if addr.scope_id() == 2));
+    /// ```
+    pub fn with_ipv6_scope(mut self, addr: Ipv6Addr, scope_id: u32) -> Self {
+        self.options_mut().ipv6_scopes.insert(addr, scope_id);
+        self
+    }
+
+    /// Load static mappings from a hosts file like `/etc/hosts`.
+    ///
+    /// Each line is in the form of `IP hostname [aliases...]`, comments and blank lines are
+    /// ignored. The mappings are merged with the ones set by
+    /// [`HickoryResolver::with_static_host`].
+    ///
+    /// IPv6 addrs could carry a numeric scope id like `fe80::1%2`, see
+    /// [`HickoryResolver::with_ipv6_scope`].
+    pub fn with_hosts_file(mut self, path: PathBuf) -> Result<Self, ResolveError> {
+        let content = std::fs::read_to_string(&path)?;
+
+        for (idx, line) in content.lines().enumerate() {
+            let line = line.split('#').next().unwrap_or_default();
+            let mut fields = line.split_whitespace();
+            let Some(ip) = fields.next() else {
+                continue;
             };
 
-            Ok(addrs)
-        })
+            let err = |msg: &str| {
+                ResolveError::Config(format!(
+                    "invalid hosts file {} at line {}: {msg}",
+                    path.display(),
+                    idx + 1
+                ))
+            };
+            let invalid = || err(&format!("invalid ip `{ip}`"));
+            let (ip, scope_id) = match ip.split_once('%') {
+                Some((ip, scope_id)) => (ip, Some(scope_id.parse::<u32>().map_err(|_| invalid())?)),
+                None => (ip, None),
+            };
+            let ip = ip.parse::<IpAddr>().map_err(|_| invalid())?;
+            match (ip, scope_id) {
+                (IpAddr::V6(ip), Some(scope_id)) => {
+                    self.options_mut().ipv6_scopes.insert(ip, scope_id);
+                }
+                (IpAddr::V4(_), Some(_)) => return Err(invalid()),
+                _ => {}
+            }
+            let mut names = fields.peekable();
+            if names.peek().is_none() {
+                return Err(err("missing hostname"));
+            }
+            for name in names {
+                self.options_mut()
+                    .static_hosts
+                    .entry(name.trim_end_matches('.').to_ascii_lowercase())
+                    .or_default()
+                    .push(ip);
+            }
+        }
+
+        Ok(self)
     }
-}
 
-/// Create a new resolver with the default configuration,
-/// which reads from `/etc/resolve.conf`.
-fn new_resolver() -> io::Result<TokioAsyncResolver> {
-    let (config, opts) = system_conf::read_system_conf().map_err(|e| {
-        io::Error::new(
-            io::ErrorKind::Other,
-            format!("error reading DNS system conf: {}", e),
-        )
-    })?;
-    Ok(TokioAsyncResolver::tokio(config, opts))
+    /// Refuse to resolve any of the given names.
+    ///
+    /// Names are matched case-insensitively, a name with leading dot like `.internal` will
+    /// block all the sub domains of it. The blocked names are never resolved, not even by
+    /// the fallback resolver.
+    pub fn with_blocklist(mut self, names: Vec<String>) -> Self {
+        self.options_mut().blocklist = names
+            .into_iter()
+            .map(|name| name.to_ascii_lowercase())
+            .collect();
+        self
+    }
+
+    /// Refuse to resolve any names except the given ones.
+    ///
+    /// Names are matched the same way as [`HickoryResolver::with_blocklist`], and the
+    /// blocklist takes precedence if both are set.
+    pub fn with_allowlist(mut self, names: Vec<String>) -> Self {
+        self.options_mut().allowlist = Some(
+            names
+                .into_iter()
+                .map(|name| name.to_ascii_lowercase())
+                .collect(),
+        );
+        self
+    }
+
+    /// Enable shuffle for the hickory resolver to make sure the ip addrs returned are shuffled.
+    ///
+    /// The rng is shared by all the clones of this resolver behind a lock, so that the
+    /// concurrent resolutions never reuse the same rng state:
+    ///
+    /// ```
+    /// use std::net::IpAddr;
+    /// use std::net::SocketAddr;
+    ///
+    /// use reqwest_hickory_resolver::HickoryResolver;
+    ///
+    /// let ips: Vec<IpAddr> = (1..=4).map(|i| format!("10.0.0.{i}").parse().unwrap()).collect();
+    /// let resolver = HickoryResolver::default()
+    ///     .with_shuffle(true)
+    ///     .with_static_host("shuffle.test", ips.clone());
+    ///
+    /// let firsts: Vec<SocketAddr> = std::thread::scope(|s| {
+    ///     let handles: Vec<_> = (0..4)
+    ///         .map(|_| {
+    ///             let resolver = resolver.clone();
+    ///             s.spawn(move || {
+    ///                 (0..250)
+    ///                     .map(|_| {
+    ///                         let addrs = resolver.resolve_with_port("shuffle.test", 0);
+    ///                         futures::executor::block_on(addrs).unwrap()[0]
+    ///                     })
+    ///                     .collect::<Vec<_>>()
+    ///             })
+    ///         })
+    ///         .collect();
+    ///     handles.into_iter().flat_map(|h| h.join().unwrap()).collect()
+    /// });
+    /// // Every addr should come first about a quarter of the time.
+    /// for ip in ips {
+    ///     let n = firsts.iter().filter(|addr| addr.ip() == ip).count();
+    ///     assert!(n > 150, "{ip} comes first {n} times out of 1000");
+    /// }
+    /// ```
+    ///
+    /// NOTES: introduce shuffle will add extra overhead like more allocations and shuffling.
+    #[cfg(feature = "shuffle")]
+    pub fn with_shuffle(mut self, shuffle: bool) -> Self {
+        if shuffle {
+            use rand::SeedableRng;
+            self = self.with_rng(Box::new(rand::rngs::SmallRng::from_entropy()));
+        }
+
+        self
+    }
+
+    /// Enable shuffle with a [`rand::rngs::SmallRng`] seeded from the given value.
+    ///
+    /// The shuffle order for a given set of addrs is fully deterministic across runs, which
+    /// is useful for tests and reproducible experiments.
+    #[cfg(feature = "shuffle")]
+    pub fn with_shuffle_seed(self, seed: u64) -> Self {
+        use rand::SeedableRng;
+        self.with_rng(Box::new(rand::rngs::SmallRng::seed_from_u64(seed)))
+    }
+
+    /// Enable shuffle with the given rng, for example a seeded one for deterministic tests
+    /// or a CSPRNG for stronger randomness.
+    ///
+    /// The rng is shared by all the clones of this resolver.
+    #[cfg(feature = "shuffle")]
+    pub fn with_rng(mut self, rng: BoxedRng) -> Self {
+        self.options_mut().rng = Some(Opaque(Arc::new(Mutex::new(rng))));
+        self
+    }
+
+    /// Enable rotation so that successive resolutions start at a different addr while keeping
+    /// a stable relative order.
+    ///
+    /// Rotation is cheaper than shuffle and will be used instead of shuffle if both are set.
+    pub fn with_rotation(mut self, enabled: bool) -> Self {
+        self.options_mut().rotation = enabled.then(Arc::default);
+        self
+    }
+
+    /// Make each name consistently prefer the same addr across resolutions for connection
+    /// affinity, the preferred addr is picked by a stable hash of the name.
+    ///
+    /// If the preferred addr disappears from a later lookup, another one will be picked the
+    /// same way. Sticky takes precedence over shuffle and rotation but not sort.
+    pub fn with_sticky(mut self, enabled: bool) -> Self {
+        self.options_mut().sticky = enabled.then(Arc::default);
+        self
+    }
+
+    /// Prefer the addrs that have historically connected fastest, the latency is fed by
+    /// [`HickoryResolver::record_latency`].
+    ///
+    /// The addrs are ordered by the EWMA of their latency after sticky, rotation and shuffle,
+    /// and unseen addrs get the average latency so they still get tried. This doesn't take
+    /// effect if sort is enabled.
+    pub fn with_latency_feedback(mut self, enabled: bool) -> Self {
+        self.options_mut().latency = enabled.then(Arc::default);
+        self
+    }
+
+    /// Record the observed round trip time of a connection to the given addr.
+    ///
+    /// This is a no-op unless [`HickoryResolver::with_latency_feedback`] is enabled, and
+    /// the latency is shared by all the clones of this resolver.
+    pub fn record_latency(&self, addr: IpAddr, rtt: Duration) {
+        if let Some(latency) = &self.options.latency {
+            latency.record(addr, rtt);
+        }
+    }
+
+    /// Sort the ip addrs so that they are returned in a deterministic order, IPv4 addrs
+    /// come before IPv6 ones and then ordered numerically.
+    ///
+    /// Sort takes precedence over shuffle and rotation, and [`HickoryResolverBuilder::build`]
+    /// will return an error if shuffle is also enabled.
+    pub fn with_sorted(mut self, enabled: bool) -> Self {
+        self.options_mut().sorted = enabled;
+        self
+    }
+
+    /// Drop the resolved addrs which are unspecified or in private, loopback, link-local and
+    /// unique local ranges to defend against DNS rebinding.
+    ///
+    /// Static hosts are not filtered. If all the addrs are dropped, the resolution fails with
+    /// [`ResolveError::Denied`] and is never delegated to the fallback resolver.
+    pub fn with_reject_private(mut self, enabled: bool) -> Self {
+        self.options_mut().reject_private = enabled;
+        self
+    }
+
+    /// Drop the resolved addrs contained in any of the given CIDRs, for example the metadata
+    /// service `169.254.169.254/32`.
+    ///
+    /// Static hosts are not filtered. If all the addrs are dropped, the resolution fails with
+    /// [`ResolveError::Denied`].
+    pub fn with_denied_cidrs(mut self, cidrs: Vec<IpNet>) -> Self {
+        self.options_mut().denied_cidrs = cidrs;
+        self
+    }
+
+    /// Interleave the IPv6 and IPv4 addrs so that the returned addrs alternate between
+    /// families as recommended by RFC 8305.
+    ///
+    /// The interleaving starts with IPv6 unless [`FamilyPreference::Ipv4First`] is set, and
+    /// shuffle (if enabled) happens within each family before interleaving.
+    pub fn with_interleave(mut self, enabled: bool) -> Self {
+        self.options_mut().interleave = enabled;
+        self
+    }
+
+    /// Return the addrs in exactly the same order as the lookup yields them.
+    ///
+    /// This disables shuffle, rotation, sort and family preference, so that the order
+    /// returned by the name servers could be relied on.
+    pub fn with_preserve_order(mut self, enabled: bool) -> Self {
+        self.options_mut().preserve_order = enabled;
+        self
+    }
+
+    /// Return at most `n` addrs for each resolution to bound the connection attempts.
+    ///
+    /// The addrs are truncated after being arranged, so the kept addrs are still shuffled
+    /// if shuffle is enabled. [`HickoryResolverBuilder::build`] will return an error if `n`
+    /// is 0.
+    pub fn with_max_addrs(mut self, n: usize) -> Self {
+        self.options_mut().max_addrs = Some(n);
+        self
+    }
+
+    /// Remove the duplicated addrs while keeping the first seen order, before shuffle and
+    /// truncation, so that reqwest won't connect to the same addr twice. Enabled by default.
+    ///
+    /// The IPv4 and IPv6 addrs are never treated as duplicated even if they are mapped.
+    ///
+    /// ```
+    /// use std::net::SocketAddr;
+    ///
+    /// use reqwest_hickory_resolver::HickoryResolver;
+    ///
+    /// let ip = "10.0.0.1".parse().unwrap();
+    /// let resolver = HickoryResolver::default().with_static_host("dup.test", vec![ip, ip]);
+    ///
+    /// let addrs = futures::executor::block_on(resolver.resolve_with_port("dup.test", 80));
+    /// assert_eq!(addrs.unwrap(), vec![SocketAddr::new(ip, 80)]);
+    ///
+    /// let resolver = resolver.with_dedup(false);
+    /// let addrs = futures::executor::block_on(resolver.resolve_with_port("dup.test", 80));
+    /// assert_eq!(addrs.unwrap().len(), 2);
+    /// ```
+    pub fn with_dedup(mut self, enabled: bool) -> Self {
+        self.options_mut().keep_duplicates = !enabled;
+        self
+    }
+
+    /// Fail the resolution with [`ResolveError::InsufficientRecords`] unless at least `n`
+    /// distinct addrs are resolved, so that a single point of failure is surfaced.
+    ///
+    /// The addrs are counted after being filtered and truncated, the fallback resolver
+    /// (if set) will be used on failure. [`HickoryResolverBuilder::build`] will return an
+    /// error if `n` is greater than the limit of [`HickoryResolver::with_max_addrs`].
+    pub fn with_min_addrs(mut self, n: usize) -> Self {
+        self.options_mut().min_addrs = Some(n);
+        self
+    }
+
+    /// Return at most `v4` IPv4 addrs and `v6` IPv6 addrs for each resolution, for example
+    /// to keep the Happy Eyeballs candidates small on dual-stack hosts.
+    ///
+    /// Each family is truncated independently after being arranged, and the limit set by
+    /// [`HickoryResolver::with_max_addrs`] still applies to the total. The resolution fails
+    /// with [`ResolveError::NoRecords`] if nothing is left, and
+    /// [`HickoryResolverBuilder::build`] will return an error if both limits are 0.
+    pub fn with_max_addrs_per_family(mut self, v4: usize, v6: usize) -> Self {
+        self.options_mut().max_addrs_per_family = Some((v4, v6));
+        self
+    }
+
+    /// Refresh the cached names in background when their remaining TTL is within `fraction`
+    /// of the TTL, while the stale but still valid addrs are served.
+    ///
+    /// The threshold is jittered per check so that the names cached at the same time won't
+    /// be refreshed at once. [`HickoryResolverBuilder::build`] will return an error if the
+    /// fraction is not in `(0, 1]`.
+    ///
+    /// The refresh is spawned on the current tokio runtime, and skipped if the resolution
+    /// isn't running inside one.
+    pub fn with_prefetch_refresh(mut self, fraction: f32) -> Self {
+        self.options_mut().prefetch_refresh = Some(fraction);
+        self
+    }
+
+    /// Rewrite the names before resolving, for example to append a suffix or map an alias.
+    ///
+    /// Everything else including the static hosts, blocklist and hooks sees the rewritten
+    /// name, an invalid rewritten name fails the resolution.
+    ///
+    /// ```
+    /// use std::sync::Arc;
+    ///
+    /// use reqwest_hickory_resolver::HickoryResolver;
+    ///
+    /// let resolver = HickoryResolver::default()
+    ///     .with_name_rewrite(Arc::new(|name: &str| name.replace(".alias", ".internal")))
+    ///     .with_static_host("db.internal", vec!["10.0.0.1".parse().unwrap()]);
+    ///
+    /// # tokio::runtime::Builder::new_current_thread().enable_all().build().unwrap().block_on(async {
+    /// let addrs = resolver.resolve_with_port("db.alias", 5432).await.unwrap();
+    /// assert_eq!(addrs, vec!["10.0.0.1:5432".parse().unwrap()]);
+    /// # });
+    /// ```
+    pub fn with_name_rewrite(mut self, rewrite: NameRewrite) -> Self {
+        self.options_mut().name_rewrite = Some(Opaque(rewrite));
+        self
+    }
+
+    /// Set a hook that will be invoked with the name and addrs after every successful
+    /// resolution, no matter the addrs come from the cache or the wire.
+    pub fn with_on_resolve(mut self, hook: ResolveHook) -> Self {
+        self.options_mut().on_resolve = Some(Opaque(hook));
+        self
+    }
+
+    /// Set a hook that will be invoked with the name and elapsed time whenever a resolution
+    /// takes longer than the given threshold, no matter it succeeds or not.
+    pub fn with_slow_query_threshold(mut self, threshold: Duration, hook: SlowQueryHook) -> Self {
+        self.options_mut().slow_query = Some((threshold, Opaque(hook)));
+        self
+    }
+
+    /// Consult the given [`DnsCache`] before querying the name servers, and populate it
+    /// with the addrs resolved from the name servers.
+    ///
+    /// Static hosts are still checked before the cache.
+    pub fn with_cache(mut self, cache: Arc<dyn DnsCache>) -> Self {
+        self.options_mut().cache = Some(Opaque(cache));
+        self
+    }
+
+    /// Clear the cache of the underlying resolver.
+    ///
+    /// This is a no-op if the resolver hasn't been initialized yet.
+    pub fn clear_cache(&self) {
+        if let Some(resolver) = self.current_resolver(false) {
+            resolver.clear_cache();
+        }
+        self.cache_index.clear();
+    }
+
+    /// Get the underlying [`AsyncResolver`], initializing it if needed.
+    ///
+    /// This is an escape hatch for capabilities not exposed by this crate, the returned
+    /// resolver shares the same cache and configuration.
+    ///
+    /// NOTES: this must be called within a Tokio runtime, otherwise
+    /// [`ResolveError::NoRuntime`] is returned. If bootstrap name servers are set, the
+    /// resolver must be initialized by [`HickoryResolver::init`] or a lookup before.
+    pub fn resolver(&self) -> Result<Arc<InnerResolver<P>>, ResolveError> {
+        self.get_or_init_resolver(None, false)
+    }
+
+    /// Returns the addrs of the name servers used by the underlying resolver, initializing
+    /// it if needed, which is helpful to check whether the intended name servers are
+    /// picked up from the system configuration.
+    ///
+    /// NOTES: the same as [`HickoryResolver::resolver`], this must be called within a Tokio
+    /// runtime.
+    pub fn nameservers(&self) -> Result<Vec<SocketAddr>, ResolveError> {
+        self.resolver()?;
+        Ok(self
+            .state
+            .read()
+            .expect("lock must be valid")
+            .as_ref()
+            .map(|state| state.nameservers.clone())
+            .unwrap_or_default())
+    }
+
+    /// Returns the number of successful and failed responses of each name server in the
+    /// form of `(addr, successes, failures)`, sorted by the addr.
+    ///
+    /// A failure is either a failed connection or an error response like timeout, so that
+    /// a flaky name server could be detected before it fails the resolutions. The name
+    /// servers that haven't been queried yet are not included.
+    pub fn nameserver_stats(&self) -> Vec<(SocketAddr, u64, u64)> {
+        self.options.nameserver_stats.snapshot()
+    }
+
+    /// Construct the underlying resolver eagerly within the current Tokio runtime.
+    ///
+    /// The resolver is constructed lazily on the first resolution by default, calling this
+    /// at startup surfaces the configuration errors early. This is a no-op if the resolver
+    /// has been constructed.
+    pub async fn init(&self) -> Result<(), ResolveError> {
+        self.bootstrapped_resolver(false).await?;
+        Ok(())
+    }
+
+    /// Rebuild the underlying resolver from the current configuration, for example after
+    /// `/etc/resolv.conf` is changed on VPN connect.
+    ///
+    /// The new resolver is swapped in atomically and shared by all the clones of this
+    /// resolver, in-flight lookups will continue on the old one. The cached names are
+    /// dropped along with the old resolver.
+    ///
+    /// NOTES: if bootstrap name servers are set, the new resolver will be bootstrapped by
+    /// the next lookup instead.
+    pub fn reload(&self) -> Result<(), ResolveError> {
+        let state = if self.need_bootstrap() {
+            None
+        } else {
+            Some(self.init_state(None)?)
+        };
+
+        *self.state.write().expect("lock must be valid") = state;
+        self.cache_index.clear();
+        Ok(())
+    }
+
+    /// Lookup the records of the given [`RecordType`] like MX, NS or CAA with the shared
+    /// resolver, so that they are cached the same way as the ip addrs.
+    pub async fn lookup(
+        &self,
+        name: &str,
+        record_type: RecordType,
+    ) -> Result<Lookup, ResolveError> {
+        self.check_blocked(name)?;
+        Ok(self
+            .bootstrapped_resolver(false)
+            .await?
+            .lookup(name, record_type)
+            .await?)
+    }
+
+    /// Lookup the TXT records of the given name with the shared resolver.
+    ///
+    /// Multi-segment TXT records will be joined into a single `String` per record.
+    pub async fn lookup_txt(&self, name: &str) -> Result<Vec<String>, ResolveError> {
+        self.check_blocked(name)?;
+        let lookup = self
+            .bootstrapped_resolver(false)
+            .await?
+            .txt_lookup(name)
+            .await?;
+
+        Ok(lookup
+            .iter()
+            .map(|txt| String::from_utf8_lossy(&txt.txt_data().concat()).into_owned())
+            .collect())
+    }
+
+    /// Lookup the SRV records of the given name with the shared resolver.
+    ///
+    /// Unlike [`Resolve::resolve`], the returned records carry the real SRV port, and the
+    /// priority and weight are preserved so callers can implement their own selection.
+    pub async fn lookup_srv(&self, name: &str) -> Result<Vec<SrvRecord>, ResolveError> {
+        self.check_blocked(name)?;
+        let lookup = self
+            .bootstrapped_resolver(false)
+            .await?
+            .srv_lookup(name)
+            .await?;
+
+        Ok(lookup
+            .iter()
+            .map(|srv| SrvRecord {
+                target: srv.target().to_utf8(),
+                port: srv.port(),
+                priority: srv.priority(),
+                weight: srv.weight(),
+            })
+            .collect())
+    }
+
+    /// Lookup the PTR records of the given ip with the shared resolver.
+    ///
+    /// Returns an empty vec if there are no PTR records for this ip.
+    pub async fn reverse_lookup(&self, ip: IpAddr) -> Result<Vec<String>, ResolveError> {
+        match self
+            .bootstrapped_resolver(false)
+            .await?
+            .reverse_lookup(ip)
+            .await
+            .map_err(ResolveError::from)
+        {
+            Ok(lookup) => Ok(lookup.iter().map(|ptr| ptr.to_utf8()).collect()),
+            Err(ResolveError::NoRecords { .. }) => Ok(Vec::new()),
+            Err(err) => Err(err),
+        }
+    }
+
+    /// Lookup the IPv4 addrs of the given name by an A query with the shared resolver,
+    /// regardless of the configured [`LookupIpStrategy`].
+    pub async fn lookup_ipv4(&self, name: &str) -> Result<Vec<Ipv4Addr>, ResolveError> {
+        self.check_blocked(name)?;
+        let lookup = self
+            .bootstrapped_resolver(false)
+            .await?
+            .ipv4_lookup(name)
+            .await?;
+
+        Ok(lookup.iter().map(|a| a.0).collect())
+    }
+
+    /// Lookup the IPv6 addrs of the given name by an AAAA query with the shared resolver,
+    /// regardless of the configured [`LookupIpStrategy`].
+    pub async fn lookup_ipv6(&self, name: &str) -> Result<Vec<Ipv6Addr>, ResolveError> {
+        self.check_blocked(name)?;
+        let lookup = self
+            .bootstrapped_resolver(false)
+            .await?
+            .ipv6_lookup(name)
+            .await?;
+
+        Ok(lookup.iter().map(|aaaa| aaaa.0).collect())
+    }
+
+    /// Lookup the ordered CNAME chain of the given name, returns the canonical names
+    /// traversed before the terminal A/AAAA records.
+    ///
+    /// Returns an empty vec if the name is not an alias. A one-off resolver that preserves
+    /// the intermediate records will be used unless
+    /// [`HickoryResolver::with_preserve_intermediates`] is enabled.
+    pub async fn lookup_cname_chain(&self, name: &str) -> Result<Vec<String>, ResolveError> {
+        self.check_blocked(name)?;
+        let resolver = if self.options.preserve_intermediates == Some(true) {
+            self.bootstrapped_resolver(false).await?
+        } else {
+            let this = self.clone().with_preserve_intermediates(true);
+            let bootstrapped = this.bootstrap().await?;
+            Arc::new(this.init_resolver(bootstrapped.as_deref())?)
+        };
+        let lookup = resolver.lookup_ip(name).await?;
+
+        let mut chain: Vec<String> = Vec::new();
+        for cname in lookup
+            .as_lookup()
+            .record_iter()
+            .filter_map(|record| record.data()?.as_cname())
+        {
+            // Both A and AAAA lookups carry the same chain.
+            let cname = cname.0.to_utf8();
+            if !chain.contains(&cname) {
+                chain.push(cname);
+            }
+        }
+        Ok(chain)
+    }
+
+    /// Lookup the ip addrs of the given name along with their remaining TTL.
+    ///
+    /// The TTL is computed from the time the lookup is valid until, so cached answers will
+    /// have a shorter TTL than fresh ones.
+    pub async fn lookup_ip_with_ttl(
+        &self,
+        name: &str,
+    ) -> Result<Vec<(IpAddr, Duration)>, ResolveError> {
+        self.check_blocked(name)?;
+        let lookup = self.lookup_ip(name, false).await?;
+        self.index(name, &lookup, None);
+
+        let ttl = lookup
+            .valid_until()
+            .saturating_duration_since(Instant::now());
+        Ok(lookup.iter().map(|ip| (ip, ttl)).collect())
+    }
+
+    /// Resolve the given names concurrently to warm up the cache.
+    ///
+    /// Returns the outcome of each name in the same order.
+    pub async fn prefetch(&self, names: &[&str]) -> Vec<Result<(), ResolveError>> {
+        futures::future::join_all(names.iter().map(|name| async move {
+            self.check_blocked(name)?;
+            let lookup = self.lookup_ip(name, false).await?;
+            self.index(name, &lookup, None);
+            Ok(())
+        }))
+        .await
+    }
+
+    /// Check whether the resolver is functional by resolving the given probe name, for
+    /// example [`DEFAULT_HEALTHCHECK_PROBE`].
+    ///
+    /// Returns `Ok` only if at least one addr is resolved. The probe is resolved the same
+    /// way as [`Resolve::resolve`], so all the options like timeout are respected.
+    pub async fn healthcheck(&self, probe: &str) -> Result<(), ResolveError> {
+        let name = probe
+            .parse::<Name>()
+            .map_err(|err| ResolveError::Other(err.to_string()))?;
+        let mut addrs = self.clone().resolve_name(&name, false).await?;
+        if addrs.next().is_none() {
+            return Err(ResolveError::NoRecords {
+                name: probe.to_string(),
+            });
+        }
+        Ok(())
+    }
+
+    /// Resolve the given names concurrently, returns the addrs of each name in the same order.
+    ///
+    /// The names are resolved the same way as [`Resolve::resolve`], and the concurrency limit
+    /// set by [`HickoryResolver::with_max_concurrency`] is respected.
+    pub async fn resolve_many(
+        &self,
+        names: &[&str],
+    ) -> Vec<(String, Result<Vec<SocketAddr>, ResolveError>)> {
+        futures::future::join_all(names.iter().map(|name| async move {
+            let res = match name.parse::<Name>() {
+                Ok(parsed) => self.clone().resolve_name(&parsed, false).await,
+                Err(err) => Err(ResolveError::Other(err.to_string())),
+            };
+            (name.to_string(), res.map(Iterator::collect))
+        }))
+        .await
+    }
+
+    /// Resolve the given name the same way as [`Resolve::resolve`], but the returned addrs
+    /// carry the given port instead of 0 so that they could be used as connection targets
+    /// directly.
+    pub async fn resolve_with_port(
+        &self,
+        name: &str,
+        port: u16,
+    ) -> Result<Vec<SocketAddr>, ResolveError> {
+        let parsed = name
+            .parse::<Name>()
+            .map_err(|err| ResolveError::Other(err.to_string()))?;
+
+        let addrs = self.clone().resolve_name(&parsed, false).await?;
+        Ok(addrs
+            .map(|mut addr| {
+                addr.set_port(port);
+                addr
+            })
+            .collect())
+    }
+
+    /// Resolve the given name and probe the reachability of each addr by a TCP connect to
+    /// the given port, so that dead endpoints could be dropped before handed to reqwest.
+    ///
+    /// The addrs are probed concurrently within the shared timeout, and the connections
+    /// are closed right after established.
+    pub async fn resolve_and_probe(
+        &self,
+        name: &str,
+        port: u16,
+        timeout: Duration,
+    ) -> Result<Vec<(SocketAddr, bool)>, ResolveError> {
+        let addrs = self.resolve_with_port(name, port).await?;
+
+        Ok(
+            futures::future::join_all(addrs.into_iter().map(|addr| async move {
+                let connect = tokio::net::TcpStream::connect(addr);
+                let reachable = matches!(tokio::time::timeout(timeout, connect).await, Ok(Ok(_)));
+                (addr, reachable)
+            }))
+            .await,
+        )
+    }
+
+    /// Resolve the given name the same way as [`Resolve::resolve`], along with whether the
+    /// answer came from the cache and the time spent.
+    ///
+    /// Whether the answer came from the cache is inferred the same way as
+    /// [`HickoryResolver::cache_stats`].
+    pub async fn resolve_detailed(&self, name: &str) -> Result<Resolution, ResolveError> {
+        let parsed = name
+            .parse::<Name>()
+            .map_err(|err| ResolveError::Other(err.to_string()))?;
+
+        let start = Instant::now();
+        let from_cache = self.cache_index.is_fresh(name);
+        let addrs = self.clone().resolve_name(&parsed, false).await?.collect();
+        Ok(Resolution {
+            addrs,
+            from_cache,
+            elapsed: start.elapsed(),
+        })
+    }
+
+    /// Resolve the given name from the name servers bypassing the cache, the fresh addrs
+    /// will be served by the following resolutions until expired.
+    ///
+    /// This is more surgical than [`HickoryResolver::clear_cache`] when only a single name
+    /// is known to be changed. Everything else like the static hosts and hooks is the same
+    /// as [`Resolve::resolve`].
+    pub async fn resolve_fresh(&self, name: &str) -> Result<Vec<SocketAddr>, ResolveError> {
+        let parsed = name
+            .parse::<Name>()
+            .map_err(|err| ResolveError::Other(err.to_string()))?;
+
+        Ok(self.clone().resolve_name(&parsed, true).await?.collect())
+    }
+
+    /// Returns a snapshot of the effective settings of this resolver, merged from the
+    /// options set by `with_*` methods and the defaults.
+    pub fn settings(&self) -> ResolverSettings {
+        let opts = self.options.opts.clone().unwrap_or_default();
+
+        ResolverSettings {
+            // Shuffle is overridden by preserve order, sort, sticky and rotation.
+            shuffle: self.is_shuffle()
+                && !self.options.preserve_order
+                && !self.options.sorted
+                && self.options.sticky.is_none()
+                && self.options.rotation.is_none(),
+            timeout: self.options.timeout,
+            query_timeout: opts.timeout,
+            max_addrs: self.options.max_addrs,
+            ip_strategy: self.options.ip_strategy.unwrap_or(opts.ip_strategy),
+            nameserver_source: self.nameserver_source(),
+        }
+    }
+
+    /// Returns the number of names currently cached, as counted by our shadow index of the
+    /// successful lookups since hickory doesn't expose its cache.
+    ///
+    /// Names are counted case-insensitively regardless of the trailing dot. Returns 0 if the
+    /// resolver hasn't been initialized yet.
+    pub fn cache_len(&self) -> usize {
+        self.cache_index.len()
+    }
+
+    /// Returns whether there is a positive cache entry of the given name that is not
+    /// expired yet, so that an external cache layer could decide when to refresh it.
+    ///
+    /// Returns false if the resolver hasn't been initialized or the name has never been
+    /// resolved.
+    pub fn is_cached_fresh(&self, name: &str) -> bool {
+        self.cache_index.is_fresh(name)
+    }
+
+    /// Save the names cached by the underlying resolver with their addrs and expiry time
+    /// into the given file, so that they could be restored by
+    /// [`HickoryResolver::load_cache`].
+    pub fn save_cache(&self, path: &Path) -> Result<(), ResolveError> {
+        self.cache_index.save(path)
+    }
+
+    /// Restore the names saved by [`HickoryResolver::save_cache`], the expired ones will
+    /// be discarded.
+    ///
+    /// The restored names are served until they are expired, and then resolved from the
+    /// name servers as usual.
+    pub fn load_cache(&self, path: &Path) -> Result<(), ResolveError> {
+        self.cache_index.load(path, self.cache_capacity())
+    }
+
+    /// Returns the hit and miss statistics of the cache.
+    ///
+    /// hickory doesn't tell whether an answer is served from its cache, so a hit is
+    /// inferred from a previous lookup of the same name that is not expired yet.
+    pub fn cache_stats(&self) -> CacheStats {
+        self.cache_index.stats()
+    }
+
+    /// Returns how long the given name will stay negatively cached, for example after an
+    /// `NXDOMAIN`, or `None` if it's not negatively cached.
+    ///
+    /// The remaining time is in seconds granularity as the negative TTL reported by hickory.
+    pub fn negative_ttl_remaining(&self, name: &str) -> Option<Duration> {
+        self.cache_index.negative_ttl_remaining(name)
+    }
+}
+
+impl<P: ConnectionProvider> Resolve for HickoryResolver<P> {
+    fn resolve(&self, name: Name) -> Resolving {
+        let mut hickory_resolver = self.clone();
+        #[cfg(feature = "tracing")]
+        let span = tracing::debug_span!("hickory_resolve", name = name.as_str());
+
+        #[cfg(feature = "opentelemetry")]
+        let question = name.as_str().to_string();
+
+        let fut = async move { Ok(hickory_resolver.resolve_name(&name, false).await?) };
+        #[cfg(feature = "tracing")]
+        let fut = tracing::Instrument::instrument(fut, span);
+        #[cfg(feature = "opentelemetry")]
+        let fut = otel_instrument(question, fut);
+
+        Box::pin(fut)
+    }
+}
+
+/// The resolver could be composed with the tower middlewares like timeouts and rate limits,
+/// the names are resolved the same way as [`Resolve::resolve`].
+#[cfg(feature = "tower")]
+impl<P: ConnectionProvider> tower_service::Service<Name> for HickoryResolver<P> {
+    type Response = Addrs;
+    type Error = ResolveError;
+    type Future = futures::future::BoxFuture<'static, Result<Addrs, ResolveError>>;
+
+    fn poll_ready(
+        &mut self,
+        _: &mut std::task::Context<'_>,
+    ) -> std::task::Poll<Result<(), ResolveError>> {
+        // Lookups are independent futures, so we are always ready.
+        std::task::Poll::Ready(Ok(()))
+    }
+
+    fn call(&mut self, name: Name) -> Self::Future {
+        let mut hickory_resolver = self.clone();
+        Box::pin(async move { hickory_resolver.resolve_name(&name, false).await })
+    }
+}
+
+impl<P: ConnectionProvider> HickoryResolver<P> {
+    /// Resolve the given name into addrs, delegating to the fallback resolver on failure
+    /// and invoking the hook on success.
+    ///
+    /// If `fresh` is true, the cache will be bypassed.
+    async fn resolve_name(&mut self, name: &Name, fresh: bool) -> Result<Addrs, ResolveError> {
+        let rewritten;
+        let name = match &self.options.name_rewrite {
+            Some(Opaque(rewrite)) => {
+                let new = rewrite(name.as_str());
+                rewritten = new.parse::<Name>().map_err(|_| {
+                    ResolveError::Other(format!("name is rewritten to an invalid one `{new}`"))
+                })?;
+                &rewritten
+            }
+            None => name,
+        };
+
+        let start = Instant::now();
+        let res = self.check_blocked(name.as_str());
+        // The routed names are still subject to our policies and hooks, only the lookup is
+        // dispatched to the routed resolver.
+        let res = match (res, self.route(name.as_str())) {
+            (Ok(()), Some(routed)) => {
+                let mut routed = routed.as_ref().clone();
+                let res = Box::pin(routed.resolve_name(name, fresh)).await;
+                res.and_then(|addrs| self.filter_addrs(name.as_str(), addrs))
+            }
+            (Ok(()), None) => self.resolve_addrs(name.as_str(), fresh).await,
+            (Err(err), _) => Err(err),
+        };
+        let res = res
+            .and_then(|addrs| check_non_empty(name.as_str(), addrs))
+            .and_then(|addrs| self.check_min_addrs(addrs));
+
+        // Delegate to the fallback resolver if we failed to resolve, except for the names
+        // and addrs refused by us which must never be resolved.
+        let res = match (res, &self.fallback) {
+            (Err(err @ (ResolveError::Blocked { .. } | ResolveError::Denied { .. })), _) => {
+                Err(err)
+            }
+            (Err(_), Some(fallback)) => {
+                let mut fallback = fallback.as_ref().clone();
+                Box::pin(fallback.resolve_name(name, fresh)).await
+            }
+            (res, _) => res,
+        };
+        if let Some((threshold, Opaque(hook))) = &self.options.slow_query {
+            let elapsed = start.elapsed();
+            if elapsed > *threshold {
+                hook(name, elapsed);
+            }
+        }
+        let addrs = res?;
+
+        match &self.options.on_resolve {
+            Some(Opaque(hook)) => {
+                let addrs = addrs.collect::<Vec<_>>();
+                hook(name, &addrs);
+                Ok(Box::new(addrs.into_iter()))
+            }
+            None => Ok(addrs),
+        }
+    }
+
+    /// Make sure there are at least the min number of distinct addrs if set.
+    fn check_min_addrs(&self, addrs: Addrs) -> Result<Addrs, ResolveError> {
+        let Some(required) = self.options.min_addrs else {
+            return Ok(addrs);
+        };
+
+        let addrs = addrs.collect::<Vec<_>>();
+        let found = addrs.iter().collect::<HashSet<_>>().len();
+        if found < required {
+            return Err(ResolveError::InsufficientRecords { found, required });
+        }
+        Ok(Box::new(addrs.into_iter()))
+    }
+
+    /// Returns the routed resolver with the longest suffix matching the given name.
+    fn route(&self, name: &str) -> Option<&Arc<HickoryResolver<P>>> {
+        if self.routes.is_empty() {
+            return None;
+        }
+
+        let name = normalize_name(name);
+        self.routes
+            .iter()
+            .find(|(suffix, _)| {
+                name == *suffix
+                    || (name.ends_with(suffix.as_str())
+                        && name[..name.len() - suffix.len()].ends_with('.'))
+            })
+            .map(|(_, resolver)| resolver)
+    }
+
+    /// Returns [`ResolveError::Blocked`] if the name is refused by the blocklist or
+    /// allowlist.
+    fn check_blocked(&self, name: &str) -> Result<(), ResolveError> {
+        let blocked = matches_name(&self.options.blocklist, name)
+            || (self.options.allowlist.as_ref()).is_some_and(|list| !matches_name(list, name));
+        if blocked {
+            return Err(ResolveError::Blocked {
+                name: name.to_string(),
+            });
+        }
+        Ok(())
+    }
+
+    /// Resolve the given name into addrs.
+    ///
+    /// If `fresh` is true, the name will be looked up from the name servers bypassing the
+    /// cache, and the fresh addrs will be served until expired.
+    async fn resolve_addrs(&mut self, name: &str, fresh: bool) -> Result<Addrs, ResolveError> {
+        if let Some(ips) = self
+            .options
+            .static_hosts
+            .get(normalize_name(name).as_ref())
+            .cloned()
+        {
+            let ips = self.dedup(ips);
+            let ips = if self.need_arrange() {
+                self.arrange(name, ips)
+            } else {
+                ips
+            };
+            return Ok(self.to_addrs(ips));
+        }
+
+        #[cfg(any(feature = "tracing", feature = "metrics"))]
+        let start = Instant::now();
+        let cache = self.options.cache.as_ref().map(|cache| cache.0.clone());
+        if let Some(cache) = cache.as_ref().filter(|_| !fresh) {
+            if let Some(ips) = cache.get(name).await {
+                #[cfg(feature = "tracing")]
+                trace_resolved(true, ips.len(), start.elapsed());
+                #[cfg(feature = "metrics")]
+                record_lookup_metrics(Some(ips.iter().copied()), true, start.elapsed());
+                let ips = self.filter(name, ips)?;
+                let ips = if self.need_arrange() {
+                    self.arrange(name, ips)
+                } else {
+                    ips
+                };
+                return Ok(self.to_addrs(ips));
+            }
+        }
+
+        let (cached, detached) = if fresh {
+            (false, None)
+        } else {
+            self.cache_index.record_access(name)
+        };
+        // The refresh is skipped if we are not polled inside a tokio runtime, since there
+        // is nowhere to spawn it.
+        let handle = tokio::runtime::Handle::try_current().ok();
+        if let (Some(fraction), Some(handle)) = (self.options.prefetch_refresh, handle) {
+            if cached && self.cache_index.start_refresh(name, fraction) {
+                // The stale but still valid addrs are served while refreshing.
+                let (this, name) = (self.clone(), name.to_string());
+                handle.spawn(async move {
+                    if this.refresh(&name).await.is_err() {
+                        this.cache_index.cancel_refresh(&name);
+                    }
+                });
+            }
+        }
+        if let Some(ips) = detached {
+            #[cfg(feature = "tracing")]
+            trace_resolved(cached, ips.len(), start.elapsed());
+            #[cfg(feature = "metrics")]
+            record_lookup_metrics(Some(ips.iter().copied()), cached, start.elapsed());
+            let ips = self.filter(name, ips)?;
+            let ips = if self.need_arrange() {
+                self.arrange(name, ips)
+            } else {
+                ips
+            };
+            return Ok(self.to_addrs(ips));
+        }
+
+        // Names with TTL override are served by us, so that the TTL of the underlying
+        // cache doesn't matter.
+        let ttl_override = self.ttl_override(name);
+        let res = if fresh {
+            self.refresh(name).await
+        } else {
+            self.lookup_ip(name, ttl_override.is_some()).await
+        };
+        #[cfg(feature = "metrics")]
+        record_lookup_metrics(
+            res.as_ref().ok().map(LookupIp::iter),
+            cached,
+            start.elapsed(),
+        );
+        let lookup = res?;
+        // reqwest will fail with a cryptic connect error on empty addrs,
+        // report the missing records explicitly instead.
+        if lookup.iter().next().is_none() {
+            return Err(ResolveError::NoRecords {
+                name: name.to_string(),
+            });
+        }
+        // The fresh lookup has been indexed by the refresh.
+        if !fresh {
+            self.index(name, &lookup, ttl_override.map(|ttl| Instant::now() + ttl));
+        }
+        if let Some(cache) = &cache {
+            let ttl = lookup
+                .valid_until()
+                .saturating_duration_since(Instant::now());
+            cache.put(name, lookup.iter().collect(), ttl).await;
+        }
+
+        #[cfg(feature = "tracing")]
+        trace_resolved(cached, lookup.iter().count(), start.elapsed());
+
+        let addrs = if !self.need_arrange() && !self.need_filter() && !self.need_dedup(&lookup) {
+            self.to_addrs(lookup)
+        } else {
+            // Collect all the addresses into a vector to filter and arrange them.
+            let ips = self.filter(name, lookup.into_iter().collect())?;
+            let ips = if self.need_arrange() {
+                self.arrange(name, ips)
+            } else {
+                ips
+            };
+            self.to_addrs(ips)
+        };
+
+        Ok(addrs)
+    }
+
+    /// Lookup the given name bypassing the cache, and record the fresh addrs as detached
+    /// so that they will be served until expired.
+    async fn refresh(&self, name: &str) -> Result<LookupIp, ResolveError> {
+        let lookup = self.lookup_ip(name, true).await?;
+        let valid_until = match self.ttl_override(name) {
+            Some(ttl) => Instant::now() + ttl,
+            None => lookup.valid_until(),
+        };
+        self.index(name, &lookup, Some(valid_until));
+        Ok(lookup)
+    }
+
+    /// Record the lookup in the cache index, as detached if `valid_until` is given.
+    fn index(&self, name: &str, lookup: &LookupIp, valid_until: Option<Instant>) {
+        // Nothing is cached by the underlying resolver, so neither by us.
+        if self.is_uncached() {
+            return;
+        }
+        match valid_until {
+            Some(valid_until) => {
+                let capacity = self.cache_capacity();
+                self.cache_index
+                    .insert_detached(name, lookup, valid_until, capacity)
+            }
+            None => self.cache_index.insert(name, lookup, self.cache_capacity()),
+        }
+    }
+
+    /// Record the name as negatively cached if the underlying resolver caches the error.
+    fn index_negative(&self, name: &str, err: &hickory_resolver::error::ResolveError) {
+        use hickory_resolver::error::ResolveErrorKind;
+
+        if self.is_uncached() {
+            return;
+        }
+        if let ResolveErrorKind::NoRecordsFound {
+            negative_ttl: Some(ttl),
+            ..
+        } = err.kind()
+        {
+            if *ttl > 0 {
+                let valid_until = Instant::now() + Duration::from_secs(u64::from(*ttl));
+                let capacity = self.cache_capacity();
+                self.cache_index
+                    .insert_negative(name, valid_until, capacity);
+            }
+        }
+    }
+
+    /// Returns whether the cache of the underlying resolver is disabled.
+    fn is_uncached(&self) -> bool {
+        self.cache_capacity() == 0
+    }
+
+    /// Returns the max number of names cached by the underlying resolver.
+    fn cache_capacity(&self) -> usize {
+        let size = self.options.cache_size;
+        size.or_else(|| self.options.opts.as_ref().map(|opts| opts.cache_size))
+            .unwrap_or_else(|| ResolverOpts::default().cache_size)
+    }
+
+    /// Returns whether the ip addrs need to be filtered.
+    fn need_filter(&self) -> bool {
+        self.options.reject_private || !self.options.denied_cidrs.is_empty()
+    }
+
+    /// Returns whether the lookup has duplicated ip addrs to be removed.
+    fn need_dedup(&self, lookup: &LookupIp) -> bool {
+        !self.options.keep_duplicates
+            && (lookup.iter().enumerate()).any(|(i, ip)| lookup.iter().take(i).any(|v| v == ip))
+    }
+
+    /// Remove the duplicated ip addrs unless disabled, the first seen ones are kept.
+    fn dedup(&self, mut ips: Vec<IpAddr>) -> Vec<IpAddr> {
+        if self.options.keep_duplicates {
+            return ips;
+        }
+
+        // The addrs are usually a handful, scanning the kept ones is cheaper than hashing.
+        let mut kept = 0;
+        for i in 0..ips.len() {
+            let ip = ips[i];
+            if !ips[..kept].contains(&ip) {
+                ips[kept] = ip;
+                kept += 1;
+            }
+        }
+        ips.truncate(kept);
+        ips
+    }
+
+    /// Filter out the duplicated and rejected ip addrs, returns error if all of them are
+    /// rejected.
+    fn filter(&self, name: &str, ips: Vec<IpAddr>) -> Result<Vec<IpAddr>, ResolveError> {
+        let mut ips = self.dedup(ips);
+        if !self.need_filter() {
+            return Ok(ips);
+        }
+
+        ips.retain(|ip| !self.is_rejected(ip));
+        if ips.is_empty() {
+            return Err(ResolveError::Denied {
+                name: name.to_string(),
+            });
+        }
+        Ok(ips)
+    }
+
+    /// Filter out the rejected addrs resolved by others like the routed resolvers, returns
+    /// error if all of them are rejected.
+    fn filter_addrs(&self, name: &str, addrs: Addrs) -> Result<Addrs, ResolveError> {
+        if !self.need_filter() {
+            return Ok(addrs);
+        }
+
+        let addrs = addrs
+            .filter(|addr| !self.is_rejected(&addr.ip()))
+            .collect::<Vec<_>>();
+        if addrs.is_empty() {
+            return Err(ResolveError::Denied {
+                name: name.to_string(),
+            });
+        }
+        Ok(Box::new(addrs.into_iter()))
+    }
+
+    /// Returns whether the ip is rejected as private or denied by the CIDRs.
+    fn is_rejected(&self, ip: &IpAddr) -> bool {
+        (self.options.reject_private && is_private(ip))
+            || self.options.denied_cidrs.iter().any(|net| net.contains(ip))
+    }
+
+    /// Convert the ip addrs into [`Addrs`], truncated to the max addrs if set.
+    ///
+    /// The scope id will be attached to the IPv6 addrs if set.
+    fn to_addrs<I>(&self, ips: I) -> Addrs
+    where
+        I: IntoIterator<Item = IpAddr>,
+        I::IntoIter: Send + 'static,
+    {
+        let max = self.options.max_addrs.unwrap_or(usize::MAX);
+        let (mut v4, mut v6) = self
+            .options
+            .max_addrs_per_family
+            .unwrap_or((usize::MAX, usize::MAX));
+        let scopes =
+            (!self.options.ipv6_scopes.is_empty()).then(|| self.options.ipv6_scopes.clone());
+        Box::new(
+            ips.into_iter()
+                .filter(move |addr| {
+                    let left = if addr.is_ipv4() { &mut v4 } else { &mut v6 };
+                    *left = match left.checked_sub(1) {
+                        Some(left) => left,
+                        None => return false,
+                    };
+                    true
+                })
+                .take(max)
+                .map(move |addr| match addr {
+                    IpAddr::V6(ip) => match scopes.as_ref().and_then(|scopes| scopes.get(&ip)) {
+                        Some(scope_id) => SocketAddr::V6(SocketAddrV6::new(ip, 0, 0, *scope_id)),
+                        None => SocketAddr::new(addr, 0),
+                    },
+                    IpAddr::V4(_) => SocketAddr::new(addr, 0),
+                }),
+        )
+    }
+
+    /// Returns the TTL override of the given name if set.
+    fn ttl_override(&self, name: &str) -> Option<Duration> {
+        if self.options.ttl_overrides.is_empty() {
+            return None;
+        }
+        let name = normalize_name(name);
+        self.options.ttl_overrides.get(name.as_ref()).copied()
+    }
+
+    /// Lookup the ip addrs of the given name, bounded by the configured timeout.
+    ///
+    /// If `fresh` is true, the cache of the underlying resolver will be bypassed.
+    ///
+    /// The time spent waiting for the concurrency limit is not counted in the timeout.
+    async fn lookup_ip(&self, name: &str, fresh: bool) -> Result<LookupIp, ResolveError> {
+        #[cfg(feature = "testing")]
+        if self.options.mock {
+            return Err(ResolveError::NoRecords {
+                name: name.to_string(),
+            });
+        }
+        // The timeout will panic outside of a Tokio runtime too.
+        ensure_runtime()?;
+        let _permit = match &self.options.concurrency {
+            Some(semaphore) => Some(semaphore.acquire().await.map_err(|_| {
+                ResolveError::Config("max concurrency must be greater than 0".to_string())
+            })?),
+            None => None,
+        };
+
+        match self.options.timeout {
+            Some(timeout) => tokio::time::timeout(timeout, self.lookup_ip_with_retry(name, fresh))
+                .await
+                .map_err(|_| ResolveError::Timeout)?,
+            None => self.lookup_ip_with_retry(name, fresh).await,
+        }
+    }
+
+    /// Lookup the ip addrs of the given name, retrying on errors.
+    ///
+    /// The error returned is from the last attempt.
+    async fn lookup_ip_with_retry(
+        &self,
+        name: &str,
+        fresh: bool,
+    ) -> Result<LookupIp, ResolveError> {
+        // hickory can't bypass or evict a single entry of its cache, so we look it up with
+        // the resolver without cache instead.
+        let resolver = self.bootstrapped_resolver(fresh).await?;
+
+        let mut attempt = 0;
+        loop {
+            match resolver.lookup_ip(name).await {
+                Err(err) => {
+                    // The resolver without cache doesn't cache anything.
+                    if !fresh {
+                        self.index_negative(name, &err);
+                    }
+                    let err = self.convert_error(err);
+                    if attempt >= self.options.retries || !err.is_retryable() {
+                        return Err(err);
+                    }
+                    attempt += 1;
+                    tokio::time::sleep(self.options.retry_backoff.delay(attempt)).await;
+                }
+                Ok(lookup) => return Ok(lookup),
+            }
+        }
+    }
+
+    /// Convert the error returned by hickory, validation failures will be classified
+    /// if DNSSEC is enabled.
+    fn convert_error(&self, err: hickory_resolver::error::ResolveError) -> ResolveError {
+        #[cfg(feature = "dnssec")]
+        if self.options.dnssec == Some(true) {
+            use hickory_resolver::error::ResolveErrorKind;
+            use hickory_resolver::proto::error::ProtoErrorKind;
+
+            if let ResolveErrorKind::Proto(proto) = err.kind() {
+                // hickory reports most of the validation failures by plain messages.
+                let failed = match proto.kind() {
+                    ProtoErrorKind::RrsigsNotPresent { .. } | ProtoErrorKind::Ring(_) => true,
+                    ProtoErrorKind::Message(msg) => DNSSEC_FAILURES.contains(msg),
+                    _ => false,
+                };
+                if failed {
+                    return ResolveError::Dnssec(proto.clone());
+                }
+            }
+        }
+
+        ResolveError::from(err)
+    }
+
+    /// Get the underlying resolver, resolving the encrypted name server with the bootstrap
+    /// name servers first if needed.
+    ///
+    /// If `fresh` is true, the resolver without cache will be returned instead.
+    async fn bootstrapped_resolver(
+        &self,
+        fresh: bool,
+    ) -> Result<Arc<InnerResolver<P>>, ResolveError> {
+        #[cfg(feature = "testing")]
+        if self.options.mock {
+            return Err(ResolveError::Other(
+                "the mock resolver only resolves the ip addrs in its map".to_string(),
+            ));
+        }
+        if let Some(resolver) = self.current_resolver(fresh) {
+            return Ok(resolver);
+        }
+        let addrs = self.bootstrap().await?;
+
+        self.get_or_init_resolver(addrs.as_deref(), fresh)
+    }
+
+    /// Returns the options to be modified, which are copied on write so that the clones
+    /// of this resolver are cheap.
+    fn options_mut(&mut self) -> &mut Options {
+        Arc::make_mut(&mut self.options)
+    }
+
+    /// Returns the underlying resolver, or the one without cache if `fresh` is true, if it
+    /// has been initialized.
+    fn current_resolver(&self, fresh: bool) -> Option<Arc<InnerResolver<P>>> {
+        let state = self.state.read().expect("lock must be valid");
+        state.as_ref().map(|state| state.resolver(fresh))
+    }
+
+    /// Returns the underlying resolver, or the one without cache if `fresh` is true,
+    /// initializing them with the bootstrapped addrs if needed.
+    fn get_or_init_resolver(
+        &self,
+        bootstrapped: Option<&[SocketAddr]>,
+        fresh: bool,
+    ) -> Result<Arc<InnerResolver<P>>, ResolveError> {
+        if let Some(resolver) = self.current_resolver(fresh) {
+            return Ok(resolver);
+        }
+
+        let mut state = self.state.write().expect("lock must be valid");
+        // The resolver might have been initialized while we are waiting for the lock.
+        if let Some(state) = state.as_ref() {
+            return Ok(state.resolver(fresh));
+        }
+        let initialized = self.init_state(bootstrapped)?;
+        let resolver = initialized.resolver(fresh);
+        *state = Some(initialized);
+        Ok(resolver)
+    }
+
+    /// Create a new [`State`] from the supplied configuration.
+    fn init_state(&self, bootstrapped: Option<&[SocketAddr]>) -> Result<State<P>, ResolveError> {
+        let (config, opts) = self.resolver_config(bootstrapped)?;
+        let mut nameservers: Vec<SocketAddr> = Vec::new();
+        for ns in config.name_servers() {
+            // System configuration has both UDP and TCP entries for the same addr.
+            if !nameservers.contains(&ns.socket_addr) {
+                nameservers.push(ns.socket_addr);
+            }
+        }
+
+        let mut fresh_opts = opts.clone();
+        fresh_opts.cache_size = 0;
+        Ok(State {
+            resolver: Arc::new(self.new_resolver(config.clone(), opts)?),
+            fresh: Arc::new(self.new_resolver(config, fresh_opts)?),
+            nameservers,
+        })
+    }
+
+    /// Returns whether the encrypted name server needs to be resolved by the bootstrap
+    /// name servers before the resolver is constructed.
+    fn need_bootstrap(&self) -> bool {
+        let (Some(_), Some(_), Some(protocol)) = (
+            &self.options.bootstrap_nameservers,
+            &self.options.tls_dns_name,
+            self.options.protocol,
+        ) else {
+            return false;
+        };
+
+        self.options.config.is_none()
+            && self.options.nameservers.is_none()
+            && protocol.protocol().is_encrypted()
+    }
+
+    /// Resolve the addrs of the encrypted name server with the bootstrap name servers.
+    ///
+    /// Returns `None` if bootstrap is not needed.
+    async fn bootstrap(&self) -> Result<Option<Vec<SocketAddr>>, ResolveError> {
+        if !self.need_bootstrap() {
+            return Ok(None);
+        }
+        let (Some(servers), Some(host), Some(protocol)) = (
+            &self.options.bootstrap_nameservers,
+            &self.options.tls_dns_name,
+            self.options.protocol,
+        ) else {
+            return Ok(None);
+        };
+
+        let config = ResolverConfig::from_parts(
+            None,
+            vec![],
+            NameServerConfigGroup::from_ips_clear(servers, 53, true),
+        );
+        let resolver = AsyncResolver::new(config, ResolverOpts::default(), self.provider.clone());
+        let lookup = resolver.lookup_ip(host.as_str()).await?;
+
+        Ok(Some(
+            lookup
+                .iter()
+                .map(|ip| SocketAddr::new(ip, protocol.default_port()))
+                .collect(),
+        ))
+    }
+
+    /// Create a new resolver from the supplied configuration.
+    fn init_resolver(
+        &self,
+        bootstrapped: Option<&[SocketAddr]>,
+    ) -> Result<InnerResolver<P>, ResolveError> {
+        let (config, opts) = self.resolver_config(bootstrapped)?;
+        self.new_resolver(config, opts)
+    }
+
+    /// Create a new resolver with the given config and opts.
+    ///
+    /// Returns [`ResolveError::NoRuntime`] if called outside of a Tokio runtime, since hickory
+    /// will panic in this case.
+    fn new_resolver(
+        &self,
+        config: ResolverConfig,
+        opts: ResolverOpts,
+    ) -> Result<InnerResolver<P>, ResolveError> {
+        ensure_runtime()?;
+
+        let provider = InterceptingProvider::new(
+            self.provider.clone(),
+            self.options.client_subnet,
+            self.options.connect_timeout,
+            self.options.case_randomization,
+            self.options.nameserver_stats.clone(),
+            self.options.bind_addr,
+        );
+        Ok(AsyncResolver::new(config, opts, provider))
+    }
+
+    /// Build the resolver configuration from the supplied options.
+    ///
+    /// The supplied [`ResolverConfig`] is preferred, otherwise we will read
+    /// the system configuration from `/etc/resolve.conf`. The supplied
+    /// [`ResolverOpts`] always take precedence over the system ones.
+    ///
+    /// The bootstrapped addrs will be used if name servers are not set.
+    fn resolver_config(
+        &self,
+        bootstrapped: Option<&[SocketAddr]>,
+    ) -> Result<(ResolverConfig, ResolverOpts), ResolveError> {
+        let (mut config, opts) = if let Some(config) = &self.options.config {
+            (config.clone(), ResolverOpts::default())
+        } else if self.options.nameservers.is_some() || self.options.protocol.is_some() {
+            let protocol = self.options.protocol.unwrap_or(DnsProtocol::Udp);
+            let tls_dns_name = self.options.tls_dns_name.as_deref();
+            let nameservers = self.options.nameservers.as_deref().or(bootstrapped);
+            let name_servers = match nameservers {
+                Some(addrs) => protocol.name_servers(addrs, tls_dns_name),
+                None if self.options.bootstrap_nameservers.is_some()
+                    && tls_dns_name.is_some()
+                    && protocol.protocol().is_encrypted() =>
+                {
+                    return Err(ResolveError::Config(
+                        "name server must be bootstrapped by a lookup first".to_string(),
+                    ));
+                }
+                None => protocol.well_known_name_servers(tls_dns_name),
+            };
+
+            (
+                ResolverConfig::from_parts(None, vec![], name_servers),
+                ResolverOpts::default(),
+            )
+        } else {
+            match (self.read_system_conf(), &self.options.fallback_config) {
+                #[cfg_attr(not(feature = "tracing"), allow(unused_variables))]
+                (Err(err), Some(config)) => {
+                    #[cfg(feature = "tracing")]
+                    tracing::warn!(
+                        "failed to read the system conf, use the fallback config: {err}"
+                    );
+                    (config.clone(), ResolverOpts::default())
+                }
+                (res, _) => res?,
+            }
+        };
+        if let Some(domains) = &self.options.search_domains {
+            let search = domains
+                .iter()
+                .map(|domain| {
+                    domain.parse::<hickory_resolver::Name>().map_err(|e| {
+                        ResolveError::Config(format!("invalid search domain `{domain}`: {e}"))
+                    })
+                })
+                .collect::<Result<Vec<_>, _>>()?;
+            // Only rebuild the config if there are existing search domains, so that
+            // the TLS client config is retained.
+            if !config.search().is_empty() {
+                config = ResolverConfig::from_parts(
+                    config.domain().cloned(),
+                    vec![],
+                    config.name_servers().to_vec(),
+                );
+            }
+            for name in search {
+                config.add_search(name);
+            }
+        }
+        if let Some(transport) = self.options.transport {
+            let protocol = transport.protocol();
+            let mut name_servers: Vec<NameServerConfig> = Vec::new();
+            for ns in config.name_servers() {
+                let mut ns = ns.clone();
+                if !ns.protocol.is_encrypted() {
+                    ns.protocol = protocol;
+                }
+                // System configuration has both UDP and TCP entries for the same addr.
+                if !name_servers
+                    .iter()
+                    .any(|v| v.socket_addr == ns.socket_addr && v.protocol == ns.protocol)
+                {
+                    name_servers.push(ns);
+                }
+            }
+            config = ResolverConfig::from_parts(
+                config.domain().cloned(),
+                config.search().to_vec(),
+                name_servers,
+            );
+        }
+        if let Some(ip) = self.options.bind_addr {
+            let name_servers = config
+                .name_servers()
+                .iter()
+                .cloned()
+                .map(|mut ns| {
+                    // Binding to an addr of another family will never work.
+                    if ns.socket_addr.is_ipv4() == ip.is_ipv4() {
+                        ns.bind_addr = Some(SocketAddr::new(ip, 0));
+                    }
+                    ns
+                })
+                .collect::<Vec<_>>();
+            config = ResolverConfig::from_parts(
+                config.domain().cloned(),
+                config.search().to_vec(),
+                name_servers,
+            );
+        }
+        let mut opts = self.options.opts.clone().unwrap_or(opts);
+        if let Some(ndots) = self.options.ndots {
+            opts.ndots = ndots;
+        }
+        #[cfg(feature = "dnssec")]
+        if let Some(enabled) = self.options.dnssec {
+            opts.validate = enabled;
+        }
+        if let Some(strategy) = self.options.ip_strategy {
+            opts.ip_strategy = strategy;
+        }
+        if let Some(attempts) = self.options.attempts {
+            opts.attempts = attempts;
+        }
+        if let Some(size) = self.options.cache_size {
+            opts.cache_size = size;
+        }
+        if let Some(enabled) = self.options.rotate_nameservers {
+            opts.shuffle_dns_servers = enabled;
+        }
+        if let Some(enabled) = self.options.preserve_intermediates {
+            opts.preserve_intermediates = enabled;
+        }
+        if let Some((min, max)) = self.options.ttl_bounds {
+            opts.positive_min_ttl = Some(min);
+            opts.positive_max_ttl = Some(max);
+            opts.negative_min_ttl = Some(min);
+            opts.negative_max_ttl = Some(max);
+        }
+        if self.options.negative_caching == Some(false) {
+            opts.negative_min_ttl = None;
+            opts.negative_max_ttl = Some(Duration::ZERO);
+        }
+
+        Ok((config, opts))
+    }
+
+    /// Returns where the name servers come from, the same way as
+    /// [`HickoryResolver::resolver_config`].
+    fn nameserver_source(&self) -> NameserverSource {
+        if self.options.config.is_some() {
+            return NameserverSource::Config;
+        }
+        if self.options.nameservers.is_some() {
+            return NameserverSource::Custom;
+        }
+        if self.options.protocol.is_some() {
+            return if self.need_bootstrap() {
+                NameserverSource::Bootstrap
+            } else {
+                NameserverSource::WellKnown
+            };
+        }
+        #[cfg(unix)]
+        if let Some(path) = (self.options.resolv_conf_path.clone())
+            .or_else(|| std::env::var_os(RESOLV_CONF_ENV).map(PathBuf::from))
+        {
+            return NameserverSource::ResolvConf(path);
+        }
+        NameserverSource::System
+    }
+
+    /// Read the system configuration, preferring the `resolv.conf` at the custom path.
+    fn read_system_conf(&self) -> Result<(ResolverConfig, ResolverOpts), ResolveError> {
+        #[cfg(unix)]
+        if let Some(path) = (self.options.resolv_conf_path.clone())
+            .or_else(|| std::env::var_os(RESOLV_CONF_ENV).map(PathBuf::from))
+        {
+            match read_resolv_conf(&path) {
+                Ok(conf) => return Ok(conf),
+                Err(err) if self.options.disable_default_fallback => return Err(err),
+                #[cfg_attr(not(feature = "tracing"), allow(unused_variables))]
+                Err(err) => {
+                    #[cfg(feature = "tracing")]
+                    tracing::warn!(
+                        path = %path.display(),
+                        "failed to read resolv.conf, fallback to the system conf: {err}"
+                    );
+                }
+            }
+        }
+
+        system_conf::read_system_conf()
+            .map_err(|e| ResolveError::Config(format!("error reading DNS system conf: {e}")))
+    }
+
+    /// Returns whether the ip addrs need to be arranged.
+    fn need_arrange(&self) -> bool {
+        if self.options.preserve_order {
+            return false;
+        }
+
+        self.is_shuffle()
+            || self.options.rotation.is_some()
+            || self.options.sticky.is_some()
+            || self.options.latency.is_some()
+            || self.options.sorted
+            || self.options.interleave
+            || !self.options.family_preference.is_none()
+    }
+
+    /// Returns whether shuffle is enabled.
+    fn is_shuffle(&self) -> bool {
+        #[cfg(feature = "shuffle")]
+        return self.options.rng.is_some();
+        #[cfg(not(feature = "shuffle"))]
+        return false;
+    }
+
+    /// Arrange the ip addrs of the given name based on the family preference, interleave,
+    /// sort, sticky, rotation and shuffle.
+    fn arrange(&mut self, name: &str, mut ips: Vec<IpAddr>) -> Vec<IpAddr> {
+        let offset = self
+            .options
+            .rotation
+            .as_ref()
+            .map(|counter| counter.fetch_add(1, Ordering::Relaxed));
+
+        let prefer_v6 = match self.options.family_preference {
+            FamilyPreference::Ipv6First => true,
+            FamilyPreference::Ipv4First => false,
+            FamilyPreference::None if self.options.interleave => true,
+            FamilyPreference::None => {
+                self.reorder(name, &mut ips, offset);
+                return ips;
+            }
+        };
+
+        let (mut preferred, mut others): (Vec<_>, Vec<_>) =
+            ips.into_iter().partition(|ip| ip.is_ipv6() == prefer_v6);
+        self.reorder(name, &mut preferred, offset);
+        self.reorder(name, &mut others, offset);
+        if !self.options.interleave {
+            preferred.extend(others);
+            return preferred;
+        }
+
+        let mut ips = Vec::with_capacity(preferred.len() + others.len());
+        let (mut preferred, mut others) = (preferred.into_iter(), others.into_iter());
+        loop {
+            match (preferred.next(), others.next()) {
+                (None, None) => return ips,
+                (a, b) => ips.extend(a.into_iter().chain(b)),
+            }
+        }
+    }
+
+    /// Reorder the ip addrs by sorting them if enabled, starting at the preferred addr if
+    /// sticky, rotating them with the given offset, or shuffling them if rotation is not
+    /// enabled. The latency feedback applies on top of them.
+    fn reorder(&mut self, name: &str, ips: &mut [IpAddr], offset: Option<usize>) {
+        if ips.is_empty() {
+            return;
+        }
+        if self.options.sorted {
+            ips.sort();
+            return;
+        }
+
+        if let Some(sticky) = &self.options.sticky {
+            sticky.reorder(name, ips);
+        } else if let Some(offset) = offset {
+            ips.rotate_left(offset % ips.len());
+        } else {
+            #[cfg(feature = "shuffle")]
+            if let Some(rng) = &self.options.rng {
+                use rand::seq::SliceRandom;
+                ips.shuffle(&mut *rng.0.lock().expect("lock must be valid"));
+            }
+        }
+        if let Some(latency) = &self.options.latency {
+            latency.reorder(ips);
+        }
+    }
+}
+
+/// SrvRecord is a SRV record returned by [`HickoryResolver::lookup_srv`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SrvRecord {
+    /// The target host of this record.
+    pub target: String,
+    /// The port of the service on the target host.
+    pub port: u16,
+    /// The priority of the target host, lower value means more preferred.
+    pub priority: u16,
+    /// The relative weight for records with the same priority.
+    pub weight: u16,
+}
+
+/// Resolution is the outcome returned by [`HickoryResolver::resolve_detailed`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Resolution {
+    /// The resolved addrs.
+    pub addrs: Vec<SocketAddr>,
+    /// Whether the addrs came from the cache.
+    pub from_cache: bool,
+    /// The time spent resolving the name.
+    pub elapsed: Duration,
+}
+
+/// ResolverSettings is the snapshot of the effective settings returned by
+/// [`HickoryResolver::settings`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[non_exhaustive]
+pub struct ResolverSettings {
+    /// Whether the resolved addrs are shuffled.
+    pub shuffle: bool,
+    /// The timeout for the whole resolution of a name.
+    pub timeout: Option<Duration>,
+    /// The timeout for each query to the name servers.
+    pub query_timeout: Duration,
+    /// The max number of addrs returned for each resolution.
+    pub max_addrs: Option<usize>,
+    /// The strategy used to query ip addrs.
+    pub ip_strategy: LookupIpStrategy,
+    /// Where the name servers come from.
+    pub nameserver_source: NameserverSource,
+}
+
+/// NameserverSource is where the name servers of [`HickoryResolver`] come from.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum NameserverSource {
+    /// The config set by [`HickoryResolver::with_config`].
+    Config,
+    /// The name servers set by [`HickoryResolver::with_nameservers`].
+    Custom,
+    /// The well-known Cloudflare name servers for the protocol set by
+    /// [`HickoryResolver::with_protocol`].
+    WellKnown,
+    /// The encrypted name server resolved by the bootstrap name servers.
+    Bootstrap,
+    /// The `resolv.conf` at the custom path, the system configuration is used if it can't
+    /// be read.
+    ResolvConf(PathBuf),
+    /// The system configuration.
+    System,
+}
+
+/// DnsProtocol is the protocol used to talk with the name servers.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(
+    feature = "serde",
+    derive(serde::Serialize, serde::Deserialize),
+    serde(rename_all = "lowercase")
+)]
+#[non_exhaustive]
+pub enum DnsProtocol {
+    /// Plain DNS over UDP.
+    Udp,
+    /// Plain DNS over TCP.
+    Tcp,
+    /// DNS over HTTPS.
+    #[cfg(feature = "dns-over-https")]
+    Https,
+    /// DNS over TLS.
+    #[cfg(any(feature = "dns-over-tls", feature = "dns-over-native-tls"))]
+    Tls,
+}
+
+impl DnsProtocol {
+    fn protocol(self) -> Protocol {
+        match self {
+            DnsProtocol::Udp => Protocol::Udp,
+            DnsProtocol::Tcp => Protocol::Tcp,
+            #[cfg(feature = "dns-over-https")]
+            DnsProtocol::Https => Protocol::Https,
+            #[cfg(any(feature = "dns-over-tls", feature = "dns-over-native-tls"))]
+            DnsProtocol::Tls => Protocol::Tls,
+        }
+    }
+
+    fn default_port(self) -> u16 {
+        match self {
+            DnsProtocol::Udp | DnsProtocol::Tcp => 53,
+            #[cfg(feature = "dns-over-https")]
+            DnsProtocol::Https => 443,
+            #[cfg(any(feature = "dns-over-tls", feature = "dns-over-native-tls"))]
+            DnsProtocol::Tls => 853,
+        }
+    }
+
+    /// Build a name server group with the well-known Cloudflare name servers.
+    ///
+    /// The given TLS server name will be used by encrypted protocols instead of the
+    /// Cloudflare one.
+    fn well_known_name_servers(self, tls_dns_name: Option<&str>) -> NameServerConfigGroup {
+        let addrs = CLOUDFLARE_IPS
+            .iter()
+            .map(|ip| SocketAddr::new(*ip, self.default_port()))
+            .collect::<Vec<_>>();
+
+        self.name_servers(&addrs, Some(tls_dns_name.unwrap_or("cloudflare-dns.com")))
+    }
+
+    /// Build a name server group with the given addrs.
+    ///
+    /// Encrypted protocols will use the ip of name server as TLS server
+    /// name if it's not supplied. UDP name servers get a TCP entry of the same addr too,
+    /// since hickory only retries the truncated answers over TCP if there is one.
+    fn name_servers(
+        self,
+        addrs: &[SocketAddr],
+        tls_dns_name: Option<&str>,
+    ) -> NameServerConfigGroup {
+        let protocol = self.protocol();
+
+        addrs
+            .iter()
+            .flat_map(|addr| {
+                let mut name_server = NameServerConfig::new(*addr, protocol);
+                if protocol.is_encrypted() {
+                    name_server.tls_dns_name = Some(
+                        tls_dns_name
+                            .map(str::to_string)
+                            .unwrap_or_else(|| addr.ip().to_string()),
+                    );
+                }
+                let tcp = (protocol == Protocol::Udp)
+                    .then(|| NameServerConfig::new(*addr, Protocol::Tcp));
+                std::iter::once(name_server).chain(tcp)
+            })
+            .collect::<Vec<_>>()
+            .into()
+    }
+}
+
+/// Backoff is the strategy to space the retries of a failed lookup, see
+/// [`HickoryResolver::with_retry_backoff`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum Backoff {
+    /// Sleep for the same duration between retries.
+    Fixed(Duration),
+    /// Sleep for `base` before the first retry and double it for each following retry,
+    /// capped at `max`.
+    Exponential {
+        /// The backoff before the first retry.
+        base: Duration,
+        /// The max backoff between retries.
+        max: Duration,
+    },
+}
+
+impl Default for Backoff {
+    fn default() -> Self {
+        Backoff::Fixed(RETRY_BACKOFF)
+    }
+}
+
+impl Backoff {
+    /// Returns the backoff before the given retry which starts from 1.
+    fn delay(self, retry: usize) -> Duration {
+        match self {
+            Backoff::Fixed(delay) => delay,
+            Backoff::Exponential { base, max } => u32::try_from(retry - 1)
+                .ok()
+                .and_then(|exp| 2u32.checked_pow(exp))
+                .and_then(|factor| base.checked_mul(factor))
+                .map_or(max, |delay| delay.min(max)),
+        }
+    }
+}
+
+/// Transport is the plain transport used to talk with the name servers, see
+/// [`HickoryResolver::with_transport`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Transport {
+    /// Plain DNS over UDP.
+    Udp,
+    /// Plain DNS over TCP.
+    Tcp,
+}
+
+impl Transport {
+    fn protocol(self) -> Protocol {
+        match self {
+            Transport::Udp => Protocol::Udp,
+            Transport::Tcp => Protocol::Tcp,
+        }
+    }
+}
+
+/// The weight of the latest sample in the latency EWMA.
+const LATENCY_EWMA_ALPHA: f64 = 0.3;
+
+/// LatencyIndex records the EWMA of the observed latency of each addr, see
+/// [`HickoryResolver::with_latency_feedback`].
+#[derive(Debug, Default)]
+struct LatencyIndex {
+    /// The EWMA latency in seconds.
+    ewma: Mutex<HashMap<IpAddr, f64>>,
+}
+
+impl LatencyIndex {
+    fn record(&self, addr: IpAddr, rtt: Duration) {
+        let rtt = rtt.as_secs_f64();
+        let mut ewma = self.ewma.lock().expect("lock must be valid");
+        ewma.entry(addr)
+            .and_modify(|v| *v = LATENCY_EWMA_ALPHA * rtt + (1.0 - LATENCY_EWMA_ALPHA) * *v)
+            .or_insert(rtt);
+    }
+
+    /// Stable sort the ip addrs by their latency, the unseen ones are treated as the
+    /// average of the seen ones.
+    fn reorder(&self, ips: &mut [IpAddr]) {
+        let ewma = self.ewma.lock().expect("lock must be valid");
+        let seen = ips.iter().filter_map(|ip| ewma.get(ip)).collect::<Vec<_>>();
+        if seen.is_empty() {
+            return;
+        }
+        let prior = seen.iter().copied().sum::<f64>() / seen.len() as f64;
+        ips.sort_by(|a, b| {
+            let a = ewma.get(a).copied().unwrap_or(prior);
+            let b = ewma.get(b).copied().unwrap_or(prior);
+            a.total_cmp(&b)
+        });
+    }
+}
+
+/// Returns [`ResolveError::NoRecords`] if there is no addr at all, since reqwest will fail
+/// with a cryptic connect error on empty addrs.
+fn check_non_empty(name: &str, addrs: Addrs) -> Result<Addrs, ResolveError> {
+    let mut addrs = addrs.peekable();
+    if addrs.peek().is_none() {
+        return Err(ResolveError::NoRecords {
+            name: name.to_string(),
+        });
+    }
+    Ok(Box::new(addrs))
+}
+
+/// Returns [`ResolveError::NoRuntime`] if we are not in a Tokio runtime.
+fn ensure_runtime() -> Result<(), ResolveError> {
+    match tokio::runtime::Handle::try_current() {
+        Ok(_) => Ok(()),
+        Err(_) => Err(ResolveError::NoRuntime),
+    }
+}
+
+/// Read and parse the `resolv.conf` at the given path.
+#[cfg(unix)]
+fn read_resolv_conf(path: &Path) -> Result<(ResolverConfig, ResolverOpts), ResolveError> {
+    let data = std::fs::read(path)?;
+    system_conf::parse_resolv_conf(data)
+        .map_err(|e| ResolveError::Config(format!("invalid resolv.conf {}: {e}", path.display())))
+}
+
+/// StickyIndex records the preferred addr of each name, see [`HickoryResolver::with_sticky`].
+#[derive(Debug, Default)]
+struct StickyIndex {
+    /// The preferred addrs keyed by the lowercased name and the family of the addrs, the
+    /// family is `None` if the addrs are mixed.
+    preferred: Mutex<HashMap<(String, Option<bool>), IpAddr>>,
+}
+
+impl StickyIndex {
+    /// Rotate the ip addrs so that the preferred addr of the given name comes first.
+    fn reorder(&self, name: &str, ips: &mut [IpAddr]) {
+        let family = ips[0].is_ipv6();
+        let family = ips
+            .iter()
+            .all(|ip| ip.is_ipv6() == family)
+            .then_some(family);
+        let key = (name.trim_end_matches('.').to_ascii_lowercase(), family);
+
+        let mut preferred = self.preferred.lock().expect("lock must be valid");
+        let idx = match preferred
+            .get(&key)
+            .and_then(|ip| ips.iter().position(|v| v == ip))
+        {
+            Some(idx) => idx,
+            None => {
+                let mut hasher = DefaultHasher::new();
+                key.0.hash(&mut hasher);
+                let idx = (hasher.finish() % ips.len() as u64) as usize;
+                preferred.insert(key, ips[idx]);
+                idx
+            }
+        };
+        ips.rotate_left(idx);
+    }
+}
+
+/// Returns whether the ip is unspecified or in private, loopback, link-local or unique
+/// local ranges.
+fn is_private(ip: &IpAddr) -> bool {
+    match ip {
+        IpAddr::V4(ip) => {
+            ip.is_private() || ip.is_loopback() || ip.is_link_local() || ip.is_unspecified()
+        }
+        IpAddr::V6(ip) => {
+            if let Some(ip) = ip.to_ipv4_mapped() {
+                return is_private(&IpAddr::V4(ip));
+            }
+            let segment = ip.segments()[0];
+            ip.is_loopback()
+                || ip.is_unspecified()
+                // Link-local addrs in fe80::/10.
+                || (segment & 0xffc0) == 0xfe80
+                // Unique local addrs in fc00::/7.
+                || (segment & 0xfe00) == 0xfc00
+        }
+    }
+}
+
+/// Returns the name lowercased without the trailing dot, which is used as the key of names
+/// everywhere. The name is borrowed as is if it's already normalized.
+pub(crate) fn normalize_name(name: &str) -> Cow<'_, str> {
+    let name = name.trim_end_matches('.');
+    if name.bytes().any(|b| b.is_ascii_uppercase()) {
+        Cow::Owned(name.to_ascii_lowercase())
+    } else {
+        Cow::Borrowed(name)
+    }
+}
+
+/// Returns whether the name matches any of the given names.
+///
+/// The given names must be lowercased, a name with leading dot will match
+/// all the sub domains of it.
+fn matches_name(names: &HashSet<String>, name: &str) -> bool {
+    if names.is_empty() {
+        return false;
+    }
+
+    let name = normalize_name(name);
+    names.contains(name.as_ref())
+        || names
+            .iter()
+            .any(|suffix| suffix.starts_with('.') && name.ends_with(suffix.as_str()))
+}
+
+/// Wrap the resolution in an OpenTelemetry span named `dns.resolve` following the semantic
+/// conventions of DNS.
+#[cfg(feature = "opentelemetry")]
+async fn otel_instrument<F>(
+    question: String,
+    fut: F,
+) -> Result<Addrs, Box<dyn std::error::Error + Send + Sync>>
+where
+    F: std::future::Future<Output = Result<Addrs, Box<dyn std::error::Error + Send + Sync>>>,
+{
+    use opentelemetry::trace::Span;
+    use opentelemetry::trace::Status;
+    use opentelemetry::trace::Tracer;
+    use opentelemetry::KeyValue;
+
+    let mut span = opentelemetry::global::tracer("reqwest-hickory-resolver").start("dns.resolve");
+    span.set_attribute(KeyValue::new("dns.question.name", question));
+
+    let res = match fut.await {
+        Ok(addrs) => {
+            let addrs = addrs.collect::<Vec<_>>();
+            span.set_attribute(KeyValue::new("dns.answer.count", addrs.len() as i64));
+            Ok(Box::new(addrs.into_iter()) as Addrs)
+        }
+        Err(err) => {
+            span.set_status(Status::error(err.to_string()));
+            Err(err)
+        }
+    };
+    span.end();
+    res
+}
+
+/// Record the metrics of a lookup that goes to the underlying resolver.
+/// Emit the event of a resolution served from either the cache or the wire.
+#[cfg(feature = "tracing")]
+fn trace_resolved(cached: bool, addrs: usize, elapsed: Duration) {
+    tracing::debug!(
+        cached,
+        addrs,
+        elapsed = ?elapsed,
+        "resolved from {}",
+        if cached { "cache" } else { "wire" }
+    );
+}
+
+/// Record the metrics of a lookup served from either the cache or the wire, `ips` is
+/// `None` if the lookup failed.
+#[cfg(feature = "metrics")]
+fn record_lookup_metrics(
+    ips: Option<impl Iterator<Item = IpAddr>>,
+    cached: bool,
+    elapsed: Duration,
+) {
+    let source = if cached { "cache" } else { "wire" };
+    metrics::counter!("hickory_resolver_lookups_total", "source" => source).increment(1);
+    metrics::histogram!("hickory_resolver_lookup_duration_seconds").record(elapsed.as_secs_f64());
+
+    match ips {
+        Some(ips) => {
+            let (v4, v6) = ips.fold((0, 0), |(v4, v6), ip| match ip {
+                IpAddr::V4(_) => (v4 + 1, v6),
+                IpAddr::V6(_) => (v4, v6 + 1),
+            });
+            metrics::counter!("hickory_resolver_addrs_total", "family" => "ipv4").increment(v4);
+            metrics::counter!("hickory_resolver_addrs_total", "family" => "ipv6").increment(v6);
+        }
+        None => metrics::counter!("hickory_resolver_errors_total").increment(1),
+    }
+}
+
+/// FamilyPreference decides which address family will be returned first.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum FamilyPreference {
+    /// Return IPv6 addrs before IPv4 addrs.
+    Ipv6First,
+    /// Return IPv4 addrs before IPv6 addrs.
+    Ipv4First,
+    /// Keep the addrs in the order returned by the lookup.
+    #[default]
+    None,
+}
+
+impl FamilyPreference {
+    fn is_none(&self) -> bool {
+        *self == FamilyPreference::None
+    }
 }
@@ -0,0 +1,412 @@
+use std::any::Any;
+use std::any::TypeId;
+use std::collections::hash_map::RandomState;
+use std::collections::HashMap;
+use std::future::Future;
+use std::hash::BuildHasher;
+use std::hash::Hasher;
+use std::io;
+use std::net::IpAddr;
+use std::net::SocketAddr;
+use std::pin::Pin;
+use std::sync::Arc;
+use std::sync::Mutex;
+use std::task::ready;
+use std::task::Context;
+use std::task::Poll;
+use std::time::Duration;
+
+use futures::stream::BoxStream;
+use futures::Stream;
+use futures::StreamExt;
+use hickory_resolver::config::NameServerConfig;
+use hickory_resolver::config::ResolverOpts;
+use hickory_resolver::error::ResolveError;
+use hickory_resolver::name_server::ConnectionProvider;
+use hickory_resolver::name_server::GenericConnector;
+use hickory_resolver::name_server::RuntimeProvider;
+use hickory_resolver::name_server::TokioConnectionProvider;
+use hickory_resolver::name_server::TokioRuntimeProvider;
+use hickory_resolver::proto::error::ProtoError;
+use hickory_resolver::proto::iocompat::AsyncIoTokioAsStd;
+use hickory_resolver::proto::op::Edns;
+use hickory_resolver::proto::op::Query;
+use hickory_resolver::proto::rr::rdata::opt::ClientSubnet;
+use hickory_resolver::proto::rr::rdata::opt::EdnsOption;
+use hickory_resolver::proto::rr::Name;
+use hickory_resolver::proto::xfer::DnsHandle;
+use hickory_resolver::proto::xfer::DnsRequest;
+use hickory_resolver::proto::xfer::DnsResponse;
+
+/// InterceptingProvider wraps the user supplied [`ConnectionProvider`] so that the outgoing
+/// requests could be adjusted before sent to the name servers, for example to attach the
+/// EDNS Client Subnet.
+#[derive(Clone)]
+pub struct InterceptingProvider<P> {
+    inner: P,
+    client_subnet: Option<ClientSubnet>,
+    connect_timeout: Option<Duration>,
+    case_randomization: bool,
+    stats: Arc<NameserverStats>,
+    /// The connector binding the sockets to the given addr, which replaces the inner
+    /// provider if it's the hickory's generic one.
+    bind: Option<GenericConnector<BindRuntimeProvider>>,
+}
+
+impl<P: ConnectionProvider> InterceptingProvider<P> {
+    pub(crate) fn new(
+        inner: P,
+        client_subnet: Option<ClientSubnet>,
+        connect_timeout: Option<Duration>,
+        case_randomization: bool,
+        stats: Arc<NameserverStats>,
+        bind_addr: Option<IpAddr>,
+    ) -> Self {
+        // hickory's generic connector ignores the `bind_addr` of name servers, so the
+        // sockets are bound by our runtime provider instead. Only the tokio provider is
+        // replaced, since the custom runtimes share the same connection type but must be
+        // kept.
+        let bind = bind_addr
+            .filter(|_| TypeId::of::<P>() == TypeId::of::<TokioConnectionProvider>())
+            .map(|ip| {
+                GenericConnector::new(BindRuntimeProvider {
+                    inner: TokioRuntimeProvider::new(),
+                    ip,
+                })
+            });
+
+        InterceptingProvider {
+            inner,
+            client_subnet,
+            connect_timeout,
+            case_randomization,
+            stats,
+            bind,
+        }
+    }
+}
+
+/// BindRuntimeProvider is the [`TokioRuntimeProvider`] binding the sockets to the given
+/// addr before connecting to the name servers.
+#[derive(Clone)]
+pub(crate) struct BindRuntimeProvider {
+    inner: TokioRuntimeProvider,
+    ip: IpAddr,
+}
+
+impl BindRuntimeProvider {
+    /// Returns the addr to bind for the given name server, the system picks the addr if
+    /// the families don't match.
+    fn local_addr(&self, server_addr: SocketAddr, port: u16) -> Option<SocketAddr> {
+        (server_addr.is_ipv4() == self.ip.is_ipv4()).then_some(SocketAddr::new(self.ip, port))
+    }
+}
+
+impl RuntimeProvider for BindRuntimeProvider {
+    type Handle = <TokioRuntimeProvider as RuntimeProvider>::Handle;
+    type Timer = <TokioRuntimeProvider as RuntimeProvider>::Timer;
+    type Udp = <TokioRuntimeProvider as RuntimeProvider>::Udp;
+    type Tcp = <TokioRuntimeProvider as RuntimeProvider>::Tcp;
+
+    fn create_handle(&self) -> Self::Handle {
+        self.inner.create_handle()
+    }
+
+    fn connect_tcp(
+        &self,
+        server_addr: SocketAddr,
+    ) -> Pin<Box<dyn Send + Future<Output = io::Result<Self::Tcp>>>> {
+        let Some(local_addr) = self.local_addr(server_addr, 0) else {
+            return self.inner.connect_tcp(server_addr);
+        };
+
+        Box::pin(async move {
+            let socket = match local_addr {
+                SocketAddr::V4(_) => tokio::net::TcpSocket::new_v4()?,
+                SocketAddr::V6(_) => tokio::net::TcpSocket::new_v6()?,
+            };
+            socket.bind(local_addr)?;
+            socket.connect(server_addr).await.map(AsyncIoTokioAsStd)
+        })
+    }
+
+    fn bind_udp(
+        &self,
+        local_addr: SocketAddr,
+        server_addr: SocketAddr,
+    ) -> Pin<Box<dyn Send + Future<Output = io::Result<Self::Udp>>>> {
+        // Keep the random port picked by hickory.
+        let local_addr = self
+            .local_addr(server_addr, local_addr.port())
+            .unwrap_or(local_addr);
+        self.inner.bind_udp(local_addr, server_addr)
+    }
+}
+
+/// NameserverStats counts the successful and failed responses of each name server.
+#[derive(Debug, Default)]
+pub(crate) struct NameserverStats {
+    counts: Mutex<HashMap<SocketAddr, (u64, u64)>>,
+}
+
+impl NameserverStats {
+    fn record(&self, addr: SocketAddr, success: bool) {
+        let mut counts = self.counts.lock().expect("lock must be valid");
+        let (successes, failures) = counts.entry(addr).or_default();
+        if success {
+            *successes += 1;
+        } else {
+            *failures += 1;
+        }
+    }
+
+    /// Returns the successes and failures of each name server sorted by the addr.
+    pub(crate) fn snapshot(&self) -> Vec<(SocketAddr, u64, u64)> {
+        let counts = self.counts.lock().expect("lock must be valid");
+        let mut stats = counts
+            .iter()
+            .map(|(addr, (successes, failures))| (*addr, *successes, *failures))
+            .collect::<Vec<_>>();
+        stats.sort_unstable_by_key(|(addr, _, _)| *addr);
+        stats
+    }
+}
+
+impl<P: ConnectionProvider> ConnectionProvider for InterceptingProvider<P> {
+    type Conn = InterceptingConn<P::Conn>;
+    type FutureConn = Pin<Box<dyn Future<Output = Result<Self::Conn, ResolveError>> + Send>>;
+    type RuntimeProvider = P::RuntimeProvider;
+
+    fn new_connection(
+        &self,
+        config: &NameServerConfig,
+        options: &ResolverOpts,
+    ) -> Self::FutureConn {
+        let fut: Pin<Box<dyn Future<Output = Result<P::Conn, ResolveError>> + Send>> =
+            match &self.bind {
+                Some(bind) => {
+                    let fut = bind.new_connection(config, options);
+                    Box::pin(async move {
+                        let conn: Box<dyn Any + Send> = Box::new(fut.await?);
+                        Ok(*conn
+                            .downcast::<P::Conn>()
+                            .expect("conn must be GenericConnection"))
+                    })
+                }
+                None => Box::pin(self.inner.new_connection(config, options)),
+            };
+        let client_subnet = self.client_subnet;
+        let connect_timeout = self.connect_timeout;
+        let case_randomization = self.case_randomization;
+        let (stats, addr) = (self.stats.clone(), config.socket_addr);
+
+        Box::pin(async move {
+            let res = match connect_timeout {
+                Some(timeout) => tokio::time::timeout(timeout, fut)
+                    .await
+                    .unwrap_or_else(|_| {
+                        Err(ResolveError::from(io::Error::new(
+                            io::ErrorKind::TimedOut,
+                            "connect to name server timed out",
+                        )))
+                    }),
+                None => fut.await,
+            };
+            let inner = res.inspect_err(|_| stats.record(addr, false))?;
+
+            Ok(InterceptingConn {
+                inner,
+                client_subnet,
+                case_randomization,
+                stats,
+                addr,
+            })
+        })
+    }
+}
+
+/// InterceptingConn is the connection created by [`InterceptingProvider`].
+#[derive(Clone)]
+pub struct InterceptingConn<C> {
+    inner: C,
+    client_subnet: Option<ClientSubnet>,
+    case_randomization: bool,
+    stats: Arc<NameserverStats>,
+    /// The addr of the name server connected to.
+    addr: SocketAddr,
+}
+
+impl<C: DnsHandle> DnsHandle for InterceptingConn<C> {
+    type Response = BoxStream<'static, Result<DnsResponse, C::Error>>;
+    type Error = C::Error;
+
+    fn is_verifying_dnssec(&self) -> bool {
+        self.inner.is_verifying_dnssec()
+    }
+
+    fn is_using_edns(&self) -> bool {
+        self.inner.is_using_edns()
+    }
+
+    fn send<R: Into<DnsRequest> + Unpin + Send + 'static>(&self, request: R) -> Self::Response {
+        let mut request = request.into();
+        if let Some(subnet) = self.client_subnet {
+            request
+                .extensions_mut()
+                .get_or_insert_with(Edns::new)
+                .options_mut()
+                .insert(EdnsOption::Subnet(subnet));
+        }
+        // DNS 0x20: the spoofed responses are unlikely to echo the randomized case of the
+        // question, so the responses are required to match it exactly.
+        let queries = if self.case_randomization {
+            for query in request.queries_mut() {
+                let name = randomize_case(query.name());
+                query.set_name(name);
+            }
+            Some(request.queries().to_vec())
+        } else {
+            None
+        };
+
+        let resp = self.inner.send(request).map(move |resp| {
+            let resp = resp?;
+            if let Some(queries) = &queries {
+                if !queries_eq_case(queries, resp.queries()) {
+                    // Not a plain message so that it's never taken as a DNSSEC failure.
+                    let err = io::Error::new(
+                        io::ErrorKind::InvalidData,
+                        "response doesn't match the 0x20 query",
+                    );
+                    return Err(ProtoError::from(err).into());
+                }
+            }
+            Ok(resp)
+        });
+        ObservedStream {
+            inner: resp,
+            stats: self.stats.clone(),
+            addr: self.addr,
+            answered: false,
+        }
+        .boxed()
+    }
+}
+
+/// ObservedStream records the responses of a name server into [`NameserverStats`].
+struct ObservedStream<S> {
+    inner: S,
+    stats: Arc<NameserverStats>,
+    addr: SocketAddr,
+    answered: bool,
+}
+
+impl<S, E> Stream for ObservedStream<S>
+where
+    S: Stream<Item = Result<DnsResponse, E>> + Unpin,
+{
+    type Item = S::Item;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        let item = ready!(self.inner.poll_next_unpin(cx));
+        match &item {
+            Some(resp) => {
+                self.answered = true;
+                self.stats.record(self.addr, resp.is_ok());
+            }
+            // The stream ends without any response on timeout.
+            None if !self.answered => {
+                self.answered = true;
+                self.stats.record(self.addr, false);
+            }
+            None => {}
+        }
+        Poll::Ready(item)
+    }
+}
+
+/// Randomize the case of the ascii letters in the given name.
+fn randomize_case(name: &Name) -> Name {
+    let mut bits = 0u64;
+    let mut left = 0;
+    let labels = name.iter().map(|label| {
+        label
+            .iter()
+            .map(|&b| {
+                if !b.is_ascii_alphabetic() {
+                    return b;
+                }
+                if left == 0 {
+                    bits = RandomState::new().build_hasher().finish();
+                    left = u64::BITS;
+                }
+                let upper = bits & 1 == 1;
+                bits >>= 1;
+                left -= 1;
+                if upper {
+                    b.to_ascii_uppercase()
+                } else {
+                    b.to_ascii_lowercase()
+                }
+            })
+            .collect::<Vec<u8>>()
+    });
+
+    match Name::from_labels(labels.collect::<Vec<_>>()) {
+        Ok(mut randomized) => {
+            randomized.set_fqdn(name.is_fqdn());
+            randomized
+        }
+        Err(_) => name.clone(),
+    }
+}
+
+/// Returns whether the given queries are the same including the case of names.
+fn queries_eq_case(sent: &[Query], received: &[Query]) -> bool {
+    sent.len() == received.len()
+        && sent.iter().zip(received).all(|(a, b)| {
+            a.name().eq_case(b.name())
+                && a.query_type() == b.query_type()
+                && a.query_class() == b.query_class()
+        })
+}
+
+#[cfg(test)]
+mod tests {
+    use hickory_resolver::proto::rr::RecordType;
+
+    use super::*;
+
+    fn query(name: &str) -> Query {
+        Query::query(Name::from_ascii(name).unwrap(), RecordType::A)
+    }
+
+    #[test]
+    fn test_randomize_case() {
+        let name = Name::from_ascii("www.Example-1.test.").unwrap();
+        let randomized = randomize_case(&name);
+        assert_eq!(randomized, name);
+        assert_eq!(randomized.is_fqdn(), name.is_fqdn());
+        // The non-letters are kept as they are.
+        assert_eq!(
+            randomized.to_ascii().to_ascii_lowercase(),
+            "www.example-1.test."
+        );
+
+        // The letters are flipped, the chance of 64 letters all in lowercase is negligible.
+        let name = Name::from_ascii(format!("{}.test.", "a".repeat(60))).unwrap();
+        assert!(!randomize_case(&name).eq_case(&name));
+    }
+
+    #[test]
+    fn test_queries_eq_case() {
+        let sent = [query("wWw.ExAmple.test.")];
+        assert!(queries_eq_case(&sent, &[query("wWw.ExAmple.test.")]));
+        assert!(!queries_eq_case(&sent, &[query("www.example.test.")]));
+        assert!(!queries_eq_case(&sent, &[]));
+
+        let mut aaaa = query("wWw.ExAmple.test.");
+        aaaa.set_query_type(RecordType::AAAA);
+        assert!(!queries_eq_case(&sent, &[aaaa]));
+    }
+}
@@ -0,0 +1,48 @@
+use std::sync::Arc;
+
+use hickory_resolver::name_server::ConnectionProvider;
+use hickory_resolver::name_server::TokioConnectionProvider;
+use reqwest::dns::Name;
+use reqwest::dns::Resolve;
+use reqwest::dns::Resolving;
+
+use crate::HickoryResolver;
+
+/// RaceResolver queries two resolvers simultaneously and returns the first successful
+/// answer, see [`HickoryResolver::race`].
+#[derive(Debug, Clone)]
+pub struct RaceResolver<P: ConnectionProvider = TokioConnectionProvider> {
+    primary: Arc<HickoryResolver<P>>,
+    secondary: Arc<HickoryResolver<P>>,
+}
+
+impl<P: ConnectionProvider> HickoryResolver<P> {
+    /// Race the given resolvers so that the name will be resolved by whichever answers first,
+    /// for example a fast-but-flaky resolver and a slow-but-reliable one.
+    ///
+    /// The error of one resolver is ignored if the other succeeds, and the slower lookup is
+    /// dropped once the first answer arrives.
+    pub fn race(
+        primary: Arc<HickoryResolver<P>>,
+        secondary: Arc<HickoryResolver<P>>,
+    ) -> RaceResolver<P> {
+        RaceResolver { primary, secondary }
+    }
+}
+
+impl<P: ConnectionProvider> Resolve for RaceResolver<P> {
+    fn resolve(&self, name: Name) -> Resolving {
+        let mut primary = self.primary.as_ref().clone();
+        let mut secondary = self.secondary.as_ref().clone();
+
+        Box::pin(async move {
+            let lookups = [
+                Box::pin(primary.resolve_name(&name, false)),
+                Box::pin(secondary.resolve_name(&name, false)),
+            ];
+            // The error of the last failed lookup is returned if both failed.
+            let (addrs, _) = futures::future::select_ok(lookups).await?;
+            Ok(addrs)
+        })
+    }
+}
@@ -0,0 +1,1141 @@
+use std::collections::HashMap;
+use std::net::IpAddr;
+use std::net::SocketAddr;
+use std::sync::atomic::AtomicBool;
+use std::sync::atomic::Ordering;
+use std::sync::Arc;
+use std::sync::Mutex;
+use std::time::Duration;
+use std::time::Instant;
+
+use futures::stream::BoxStream;
+use futures::StreamExt;
+use hickory_resolver::config::LookupIpStrategy;
+use hickory_resolver::config::NameServerConfig;
+use hickory_resolver::config::ResolverOpts;
+use hickory_resolver::name_server::ConnectionProvider;
+use hickory_resolver::name_server::TokioRuntimeProvider;
+use hickory_resolver::proto::op::Message;
+use hickory_resolver::proto::op::MessageType;
+use hickory_resolver::proto::op::OpCode;
+use hickory_resolver::proto::op::ResponseCode;
+use hickory_resolver::proto::rr::rdata::A;
+use hickory_resolver::proto::rr::rdata::AAAA;
+use hickory_resolver::proto::rr::rdata::SOA;
+use hickory_resolver::proto::rr::RData;
+use hickory_resolver::proto::rr::Record;
+use hickory_resolver::proto::xfer::DnsHandle;
+use hickory_resolver::proto::xfer::DnsRequest;
+use hickory_resolver::proto::xfer::DnsResponse;
+
+use crate::Backoff;
+use crate::FamilyPreference;
+use crate::HickoryResolver;
+use crate::RecordType;
+use crate::ResolveError;
+
+/// The name server the fake resolvers are configured with.
+const NAMESERVER: &str = "192.0.2.53:53";
+
+/// The TTL of the records answered by [`FakeProvider`].
+const TTL: u32 = 86400;
+
+/// FakeProvider answers the queries from the records in memory without touching the
+/// network, and records the queries received by each name server.
+#[derive(Clone, Default)]
+struct FakeProvider {
+    server: Arc<FakeServer>,
+}
+
+#[derive(Default)]
+struct FakeServer {
+    records: Mutex<HashMap<String, Vec<IpAddr>>>,
+    /// The queried names and the name servers they are sent to.
+    queries: Mutex<Vec<(SocketAddr, String)>>,
+    /// Whether the queries are never answered.
+    blackhole: AtomicBool,
+    /// Whether the queries fail with timeout right away.
+    timing_out: AtomicBool,
+    /// Whether the question is lowercased in the responses instead of echoed.
+    lowercase: AtomicBool,
+}
+
+impl FakeProvider {
+    fn with_record(self, name: &str, ips: &[&str]) -> Self {
+        self.set_record(name, ips);
+        self
+    }
+
+    fn set_record(&self, name: &str, ips: &[&str]) {
+        let ips = ips.iter().map(|ip| ip.parse().unwrap()).collect();
+        let mut records = self.server.records.lock().unwrap();
+        records.insert(name.to_string(), ips);
+    }
+
+    fn blackhole(self) -> Self {
+        self.server.blackhole.store(true, Ordering::Relaxed);
+        self
+    }
+
+    fn timing_out(self) -> Self {
+        self.server.timing_out.store(true, Ordering::Relaxed);
+        self
+    }
+
+    fn lowercase(self) -> Self {
+        self.server.lowercase.store(true, Ordering::Relaxed);
+        self
+    }
+
+    /// Returns the number of queries of the given name of any record type.
+    fn queries(&self, name: &str) -> usize {
+        let queries = self.server.queries.lock().unwrap();
+        queries.iter().filter(|(_, v)| v == name).count()
+    }
+
+    /// Returns the number of queries received by each name server.
+    fn queries_by_server(&self) -> HashMap<SocketAddr, usize> {
+        let queries = self.server.queries.lock().unwrap();
+        queries
+            .iter()
+            .fold(HashMap::new(), |mut counts, (addr, _)| {
+                *counts.entry(*addr).or_default() += 1;
+                counts
+            })
+    }
+
+    fn resolver(&self) -> HickoryResolver<FakeProvider> {
+        HickoryResolver::from_provider(self.clone())
+            .with_nameservers(vec![NAMESERVER.parse().unwrap()])
+    }
+}
+
+impl ConnectionProvider for FakeProvider {
+    type Conn = FakeConn;
+    type FutureConn =
+        futures::future::Ready<Result<FakeConn, hickory_resolver::error::ResolveError>>;
+    type RuntimeProvider = TokioRuntimeProvider;
+
+    fn new_connection(&self, config: &NameServerConfig, _: &ResolverOpts) -> Self::FutureConn {
+        futures::future::ready(Ok(FakeConn {
+            server: self.server.clone(),
+            addr: config.socket_addr,
+        }))
+    }
+}
+
+#[derive(Clone)]
+struct FakeConn {
+    server: Arc<FakeServer>,
+    addr: SocketAddr,
+}
+
+impl DnsHandle for FakeConn {
+    type Response = BoxStream<'static, Result<DnsResponse, Self::Error>>;
+    type Error = hickory_resolver::error::ResolveError;
+
+    fn send<R: Into<DnsRequest> + Unpin + Send + 'static>(&self, request: R) -> Self::Response {
+        let request = request.into();
+        if self.server.blackhole.load(Ordering::Relaxed) {
+            return futures::stream::pending().boxed();
+        }
+        if self.server.timing_out.load(Ordering::Relaxed) {
+            self.record(&request);
+            let err = hickory_resolver::error::ResolveErrorKind::Timeout.into();
+            return futures::stream::once(futures::future::ready(Err(err))).boxed();
+        }
+
+        futures::stream::once(futures::future::ready(self.answer(&request))).boxed()
+    }
+}
+
+impl FakeConn {
+    /// Record the query received, returns the queried name.
+    fn record(&self, request: &DnsRequest) -> String {
+        let name = request.queries()[0]
+            .name()
+            .to_utf8()
+            .trim_end_matches('.')
+            .to_ascii_lowercase();
+        let mut queries = self.server.queries.lock().unwrap();
+        queries.push((self.addr, name.clone()));
+        name
+    }
+
+    fn answer(
+        &self,
+        request: &DnsRequest,
+    ) -> Result<DnsResponse, hickory_resolver::error::ResolveError> {
+        let mut query = request.queries()[0].clone();
+        if self.server.lowercase.load(Ordering::Relaxed) {
+            let name = query.name().to_lowercase();
+            query.set_name(name);
+        }
+        let name = self.record(request);
+        let ips = self.server.records.lock().unwrap().get(&name).cloned();
+
+        let mut msg = Message::new();
+        msg.set_id(request.id())
+            .set_message_type(MessageType::Response)
+            .set_op_code(OpCode::Query)
+            .set_recursion_desired(true)
+            .set_recursion_available(true)
+            .add_query(query.clone());
+        match ips {
+            Some(ips) => {
+                for ip in ips {
+                    let rdata = match (ip, query.query_type()) {
+                        (IpAddr::V4(ip), RecordType::A) => RData::A(A(ip)),
+                        (IpAddr::V6(ip), RecordType::AAAA) => RData::AAAA(AAAA(ip)),
+                        _ => continue,
+                    };
+                    msg.add_answer(Record::from_rdata(query.name().clone(), TTL, rdata));
+                }
+            }
+            None => {
+                msg.set_response_code(ResponseCode::NXDomain);
+            }
+        }
+        // The SOA is required to cache the negative answers.
+        if msg.answers().is_empty() {
+            let zone = hickory_resolver::Name::from_ascii("test.").unwrap();
+            let soa = SOA::new(zone.clone(), zone.clone(), 1, 3600, 600, 86400, 60);
+            msg.add_name_server(Record::from_rdata(zone, 60, RData::SOA(soa)));
+        }
+
+        Ok(DnsResponse::from_message(msg)?)
+    }
+}
+
+/// Resolve the given name the same way as [`reqwest::dns::Resolve::resolve`].
+async fn resolve<P: ConnectionProvider>(
+    resolver: &HickoryResolver<P>,
+    name: &str,
+) -> Result<Vec<SocketAddr>, ResolveError> {
+    resolver.resolve_with_port(name, 0).await
+}
+
+/// Returns the addrs of the given ips with port 0 as resolved by [`resolve`].
+fn socket_addrs(ips: &[&str]) -> Vec<SocketAddr> {
+    ips.iter()
+        .map(|ip| SocketAddr::new(ip.parse().unwrap(), 0))
+        .collect()
+}
+
+#[tokio::test]
+async fn test_ip_strategy_ipv4_only() {
+    let provider = FakeProvider::default().with_record("dual.test", &["10.0.0.1", "fd00::1"]);
+
+    let resolver = provider
+        .resolver()
+        .with_ip_strategy(LookupIpStrategy::Ipv4AndIpv6);
+    let addrs = resolve(&resolver, "dual.test").await.unwrap();
+    assert_eq!(addrs.len(), 2);
+
+    let resolver = provider
+        .resolver()
+        .with_ip_strategy(LookupIpStrategy::Ipv4Only);
+    let addrs = resolve(&resolver, "dual.test").await.unwrap();
+    assert_eq!(addrs, vec!["10.0.0.1:0".parse().unwrap()]);
+}
+
+#[tokio::test]
+async fn test_static_host_trailing_dot() {
+    let resolver = FakeProvider::default()
+        .resolver()
+        .with_static_host("a.test", vec!["10.0.0.1".parse().unwrap()]);
+
+    let addrs = resolve(&resolver, "a.test.").await.unwrap();
+    assert_eq!(addrs, vec!["10.0.0.1:0".parse().unwrap()]);
+}
+
+#[tokio::test]
+async fn test_clear_cache() {
+    let provider = FakeProvider::default().with_record("a.test", &["10.0.0.1"]);
+    let resolver = provider
+        .resolver()
+        .with_ip_strategy(LookupIpStrategy::Ipv4Only);
+
+    resolve(&resolver, "a.test").await.unwrap();
+    resolve(&resolver, "a.test").await.unwrap();
+    assert_eq!(provider.queries("a.test"), 1);
+
+    resolver.clear_cache();
+    resolve(&resolver, "a.test").await.unwrap();
+    assert_eq!(provider.queries("a.test"), 2);
+}
+
+#[tokio::test]
+async fn test_timeout() {
+    let provider = FakeProvider::default().blackhole();
+    let resolver = provider.resolver().with_timeout(Duration::from_millis(100));
+
+    let start = Instant::now();
+    let res = resolve(&resolver, "a.test").await;
+    assert!(matches!(res, Err(ResolveError::Timeout)), "{res:?}");
+    assert!(start.elapsed() < Duration::from_secs(1));
+}
+
+#[tokio::test]
+async fn test_retries() {
+    let resolver = |provider: &FakeProvider, retries: usize| {
+        provider
+            .resolver()
+            .with_ip_strategy(LookupIpStrategy::Ipv4Only)
+            .with_negative_caching(false)
+            .with_retries(retries)
+            .with_retry_backoff(Backoff::Fixed(Duration::from_millis(10)))
+    };
+
+    // Timeouts are retried, each lookup is made of several queries by hickory.
+    let provider = FakeProvider::default().timing_out();
+    let res = resolve(&resolver(&provider, 0), "a.test").await;
+    assert!(matches!(res, Err(ResolveError::Timeout)), "{res:?}");
+    let queries = provider.queries("a.test");
+    let res = resolve(&resolver(&provider, 2), "a.test").await;
+    assert!(matches!(res, Err(ResolveError::Timeout)), "{res:?}");
+    assert_eq!(provider.queries("a.test"), queries * 4);
+
+    // Definitive answers are not.
+    let provider = FakeProvider::default();
+    let res = resolve(&resolver(&provider, 2), "missing.test").await;
+    assert!(
+        matches!(res, Err(ResolveError::NoRecords { .. })),
+        "{res:?}"
+    );
+    assert_eq!(provider.queries("missing.test"), 1);
+}
+
+#[tokio::test]
+async fn test_negative_caching() {
+    let provider = FakeProvider::default();
+    let resolver = provider
+        .resolver()
+        .with_ip_strategy(LookupIpStrategy::Ipv4Only);
+    for _ in 0..2 {
+        let res = resolve(&resolver, "missing.test").await;
+        assert!(
+            matches!(res, Err(ResolveError::NoRecords { .. })),
+            "{res:?}"
+        );
+    }
+    assert_eq!(provider.queries("missing.test"), 1);
+
+    let resolver = provider
+        .resolver()
+        .with_ip_strategy(LookupIpStrategy::Ipv4Only)
+        .with_negative_caching(false);
+    for _ in 0..2 {
+        let res = resolve(&resolver, "missing-too.test").await;
+        assert!(
+            matches!(res, Err(ResolveError::NoRecords { .. })),
+            "{res:?}"
+        );
+    }
+    assert_eq!(provider.queries("missing-too.test"), 2);
+}
+
+#[tokio::test]
+async fn test_ttl_bounds() {
+    let provider = FakeProvider::default().with_record("a.test", &["10.0.0.1"]);
+    let resolver = provider
+        .resolver()
+        .with_ip_strategy(LookupIpStrategy::Ipv4Only)
+        .with_ttl_bounds(Duration::ZERO, Duration::from_secs(1));
+
+    resolve(&resolver, "a.test").await.unwrap();
+    resolve(&resolver, "a.test").await.unwrap();
+    assert_eq!(provider.queries("a.test"), 1);
+
+    tokio::time::sleep(Duration::from_millis(1100)).await;
+    resolve(&resolver, "a.test").await.unwrap();
+    assert_eq!(provider.queries("a.test"), 2);
+}
+
+#[tokio::test]
+async fn test_resolve_fresh() {
+    let provider = FakeProvider::default().with_record("a.test", &["10.0.0.1"]);
+    let resolver = provider
+        .resolver()
+        .with_ip_strategy(LookupIpStrategy::Ipv4Only);
+
+    resolve(&resolver, "a.test").await.unwrap();
+    provider.set_record("a.test", &["10.0.0.2"]);
+    let addrs = resolve(&resolver, "a.test").await.unwrap();
+    assert_eq!(addrs, vec!["10.0.0.1:0".parse().unwrap()]);
+
+    let addrs = resolver.resolve_fresh("a.test").await.unwrap();
+    assert_eq!(addrs, vec!["10.0.0.2:0".parse().unwrap()]);
+    // The fresh addrs are served by the following resolutions.
+    let addrs = resolve(&resolver, "a.test").await.unwrap();
+    assert_eq!(addrs, vec!["10.0.0.2:0".parse().unwrap()]);
+}
+
+#[tokio::test]
+async fn test_resolve_fresh_repeatedly() {
+    let provider = FakeProvider::default().with_record("a.test", &["10.0.0.1"]);
+    let resolver = provider
+        .resolver()
+        .with_ip_strategy(LookupIpStrategy::Ipv4Only);
+
+    for _ in 0..3 {
+        resolver.resolve_fresh("a.test").await.unwrap();
+    }
+    assert_eq!(provider.queries("a.test"), 3);
+    assert_eq!(
+        resolver.nameservers().unwrap(),
+        vec!["192.0.2.53:53".parse().unwrap()]
+    );
+}
+
+#[tokio::test]
+async fn test_dedup() {
+    let provider =
+        FakeProvider::default().with_record("dup.test", &["10.0.0.1", "10.0.0.2", "10.0.0.1"]);
+    let resolver = provider
+        .resolver()
+        .with_ip_strategy(LookupIpStrategy::Ipv4Only);
+    let addrs = resolve(&resolver, "dup.test").await.unwrap();
+    assert_eq!(
+        addrs,
+        vec!["10.0.0.1:0".parse().unwrap(), "10.0.0.2:0".parse().unwrap()]
+    );
+
+    let resolver = provider
+        .resolver()
+        .with_ip_strategy(LookupIpStrategy::Ipv4Only)
+        .with_dedup(false);
+    let addrs = resolve(&resolver, "dup.test").await.unwrap();
+    assert_eq!(addrs.len(), 3);
+}
+
+#[tokio::test]
+async fn test_case_randomization() {
+    let name = "case-randomization.test";
+    let provider = FakeProvider::default().with_record(name, &["10.0.0.1"]);
+    let resolver = provider
+        .resolver()
+        .with_ip_strategy(LookupIpStrategy::Ipv4Only)
+        .with_case_randomization(true);
+    let addrs = resolve(&resolver, name).await.unwrap();
+    assert_eq!(addrs, vec!["10.0.0.1:0".parse().unwrap()]);
+
+    // The responses not echoing the randomized case are rejected.
+    let provider = FakeProvider::default()
+        .with_record(name, &["10.0.0.1"])
+        .lowercase();
+    let resolver = provider
+        .resolver()
+        .with_ip_strategy(LookupIpStrategy::Ipv4Only)
+        .with_case_randomization(true)
+        .with_attempts(1);
+    let res = resolve(&resolver, name).await;
+    assert!(res.is_err(), "{res:?}");
+    #[cfg(feature = "dnssec")]
+    assert!(!matches!(res, Err(ResolveError::Dnssec(_))), "{res:?}");
+
+    let resolver = provider
+        .resolver()
+        .with_ip_strategy(LookupIpStrategy::Ipv4Only);
+    assert!(resolve(&resolver, name).await.is_ok());
+}
+
+#[tokio::test]
+async fn test_uncached() {
+    let provider = FakeProvider::default().with_record("a.test", &["10.0.0.1"]);
+    // The same as `HickoryResolver::uncached` with the fake provider.
+    let resolver = provider
+        .resolver()
+        .with_ip_strategy(LookupIpStrategy::Ipv4Only)
+        .with_cache_size(0);
+
+    resolve(&resolver, "a.test").await.unwrap();
+    resolve(&resolver, "a.test").await.unwrap();
+    assert_eq!(provider.queries("a.test"), 2);
+}
+
+#[tokio::test]
+async fn test_rotate_nameservers() {
+    let servers: Vec<SocketAddr> = vec![
+        "192.0.2.53:53".parse().unwrap(),
+        "192.0.2.54:53".parse().unwrap(),
+    ];
+    let provider = FakeProvider::default();
+    let resolver = HickoryResolver::from_provider(provider.clone())
+        .with_nameservers(servers.clone())
+        .with_ip_strategy(LookupIpStrategy::Ipv4Only)
+        .with_rotate_nameservers(true);
+
+    for i in 0..32 {
+        let name = format!("host-{i}.test");
+        provider.set_record(&name, &["10.0.0.1"]);
+        resolve(&resolver, &name).await.unwrap();
+    }
+    let counts = provider.queries_by_server();
+    for server in servers {
+        assert!(
+            counts.get(&server).copied().unwrap_or_default() > 0,
+            "{counts:?}"
+        );
+    }
+}
+
+#[test]
+fn test_backoff_delay() {
+    let fixed = Backoff::Fixed(Duration::from_millis(100));
+    assert_eq!(fixed.delay(1), Duration::from_millis(100));
+    assert_eq!(fixed.delay(5), Duration::from_millis(100));
+
+    let exp = Backoff::Exponential {
+        base: Duration::from_millis(100),
+        max: Duration::from_secs(1),
+    };
+    assert_eq!(exp.delay(1), Duration::from_millis(100));
+    assert_eq!(exp.delay(2), Duration::from_millis(200));
+    assert_eq!(exp.delay(4), Duration::from_millis(800));
+    assert_eq!(exp.delay(5), Duration::from_secs(1));
+    // The factor overflows.
+    assert_eq!(exp.delay(64), Duration::from_secs(1));
+}
+
+#[test]
+fn test_with_nameservers_str() {
+    let resolver = HickoryResolver::default()
+        .with_nameservers_str(" 8.8.8.8, 1.1.1.1:5353,,[2001:db8::1]:53 ")
+        .unwrap();
+    assert_eq!(
+        resolver.options.nameservers,
+        Some(vec![
+            "8.8.8.8:53".parse().unwrap(),
+            "1.1.1.1:5353".parse().unwrap(),
+            "[2001:db8::1]:53".parse().unwrap(),
+        ])
+    );
+
+    let res = HickoryResolver::default().with_nameservers_str("8.8.8.8,not-an-ip");
+    assert!(matches!(res, Err(ResolveError::Config(msg)) if msg.contains("not-an-ip")));
+}
+
+#[test]
+fn test_with_hosts_file() {
+    let path = std::env::temp_dir().join(format!("hickory-hosts-{}", std::process::id()));
+    std::fs::write(
+        &path,
+        "# comment\n\n10.0.0.1 a.test b.test # trailing comment\n10.0.0.2 A.TEST\nfe80::1%2 link.test\n",
+    )
+    .unwrap();
+    let resolver = HickoryResolver::default()
+        .with_hosts_file(path.clone())
+        .unwrap();
+    let hosts = &resolver.options.static_hosts;
+    assert_eq!(
+        hosts["a.test"],
+        vec![
+            "10.0.0.1".parse::<IpAddr>().unwrap(),
+            "10.0.0.2".parse().unwrap()
+        ]
+    );
+    assert_eq!(hosts["b.test"], vec!["10.0.0.1".parse::<IpAddr>().unwrap()]);
+    assert_eq!(resolver.options.ipv6_scopes[&"fe80::1".parse().unwrap()], 2);
+
+    for (content, msg) in [
+        ("not-an-ip a.test\n", "invalid ip"),
+        ("10.0.0.1\n", "missing hostname"),
+        ("10.0.0.1%2 a.test\n", "invalid ip"),
+    ] {
+        std::fs::write(&path, content).unwrap();
+        let res = HickoryResolver::default().with_hosts_file(path.clone());
+        assert!(
+            matches!(&res, Err(ResolveError::Config(err)) if err.contains(msg) && err.contains("line 1")),
+            "{content}: {res:?}"
+        );
+    }
+    std::fs::remove_file(path).unwrap();
+}
+
+#[test]
+fn test_udp_name_servers_with_tcp() {
+    use hickory_resolver::config::Protocol;
+
+    use crate::DnsProtocol;
+
+    let addrs: Vec<SocketAddr> = vec![
+        "10.0.0.1:53".parse().unwrap(),
+        "10.0.0.2:5353".parse().unwrap(),
+    ];
+    let servers = DnsProtocol::Udp.name_servers(&addrs, None);
+    let servers = servers
+        .iter()
+        .map(|ns| (ns.socket_addr, ns.protocol))
+        .collect::<Vec<_>>();
+    assert_eq!(
+        servers,
+        vec![
+            (addrs[0], Protocol::Udp),
+            (addrs[0], Protocol::Tcp),
+            (addrs[1], Protocol::Udp),
+            (addrs[1], Protocol::Tcp),
+        ]
+    );
+
+    let servers = DnsProtocol::Tcp.name_servers(&addrs, None);
+    assert!(servers.iter().all(|ns| ns.protocol == Protocol::Tcp));
+    assert_eq!(servers.len(), 2);
+}
+
+#[tokio::test]
+async fn test_blocklist() {
+    let provider = FakeProvider::default().with_record("blocked.test", &["10.0.0.1"]);
+    let fallback = provider.resolver().shared();
+    let resolver = provider
+        .resolver()
+        .with_blocklist(vec!["blocked.test".to_string()])
+        .with_fallback(fallback);
+
+    let res = resolve(&resolver, "blocked.test").await;
+    assert!(matches!(res, Err(ResolveError::Blocked { .. })), "{res:?}");
+    let res = resolver.lookup_ip_with_ttl("blocked.test").await;
+    assert!(matches!(res, Err(ResolveError::Blocked { .. })), "{res:?}");
+    let res = resolver.prefetch(&["blocked.test"]).await;
+    assert!(
+        matches!(res[..], [Err(ResolveError::Blocked { .. })]),
+        "{res:?}"
+    );
+    let res = resolver.resolve_fresh("blocked.test").await;
+    assert!(matches!(res, Err(ResolveError::Blocked { .. })), "{res:?}");
+
+    let res = resolver.lookup("blocked.test", RecordType::A).await;
+    assert!(matches!(res, Err(ResolveError::Blocked { .. })), "{res:?}");
+    let res = resolver.lookup_txt("blocked.test").await;
+    assert!(matches!(res, Err(ResolveError::Blocked { .. })), "{res:?}");
+    let res = resolver.lookup_srv("blocked.test").await;
+    assert!(matches!(res, Err(ResolveError::Blocked { .. })), "{res:?}");
+    let res = resolver.lookup_ipv4("blocked.test").await;
+    assert!(matches!(res, Err(ResolveError::Blocked { .. })), "{res:?}");
+    let res = resolver.lookup_ipv6("blocked.test").await;
+    assert!(matches!(res, Err(ResolveError::Blocked { .. })), "{res:?}");
+    let res = resolver.lookup_cname_chain("blocked.test").await;
+    assert!(matches!(res, Err(ResolveError::Blocked { .. })), "{res:?}");
+    assert_eq!(provider.queries("blocked.test"), 0);
+}
+
+#[tokio::test]
+async fn test_routed_resolver_with_policies() {
+    let provider = FakeProvider::default()
+        .with_record("x.internal", &["10.0.0.1"])
+        .with_record("y.internal", &["10.0.0.2"]);
+    let resolved = Arc::new(Mutex::new(Vec::new()));
+    let hook_resolved = resolved.clone();
+    let resolver = provider
+        .resolver()
+        .with_ip_strategy(LookupIpStrategy::Ipv4Only)
+        .with_allowlist(vec!["ok.test".to_string(), "y.internal".to_string()])
+        .with_routed_resolver(
+            "internal",
+            provider
+                .resolver()
+                .with_ip_strategy(LookupIpStrategy::Ipv4Only)
+                .shared(),
+        )
+        .with_on_resolve(Arc::new(move |name, addrs| {
+            hook_resolved
+                .lock()
+                .unwrap()
+                .push((name.as_str().to_string(), addrs.to_vec()));
+        }));
+
+    let res = resolve(&resolver, "x.internal").await;
+    assert!(matches!(res, Err(ResolveError::Blocked { .. })), "{res:?}");
+    assert_eq!(provider.queries("x.internal"), 0);
+
+    let addrs = resolve(&resolver, "y.internal").await.unwrap();
+    assert_eq!(addrs, vec!["10.0.0.2:0".parse().unwrap()]);
+    assert_eq!(
+        *resolved.lock().unwrap(),
+        vec![("y.internal".to_string(), addrs)]
+    );
+}
+
+#[tokio::test]
+async fn test_routed_resolver_with_filters() {
+    let provider = FakeProvider::default().with_record("metadata.internal", &["169.254.169.254"]);
+    let resolver = provider
+        .resolver()
+        .with_denied_cidrs(vec!["169.254.169.254/32".parse().unwrap()])
+        .with_routed_resolver("internal", provider.resolver().shared());
+
+    let res = resolve(&resolver, "metadata.internal").await;
+    assert!(matches!(res, Err(ResolveError::Denied { .. })), "{res:?}");
+}
+
+#[tokio::test]
+async fn test_denied_without_fallback() {
+    let provider = FakeProvider::default().with_record("metadata.test", &["169.254.169.254"]);
+    let fallback = provider.resolver().shared();
+    let resolver = provider
+        .resolver()
+        .with_denied_cidrs(vec!["169.254.169.254/32".parse().unwrap()])
+        .with_fallback(fallback);
+
+    let res = resolve(&resolver, "metadata.test").await;
+    assert!(matches!(res, Err(ResolveError::Denied { .. })), "{res:?}");
+}
+
+#[tokio::test]
+async fn test_private_without_fallback() {
+    let provider = FakeProvider::default().with_record("rebind.test", &["10.0.0.1"]);
+    let fallback = provider.resolver().shared();
+    let resolver = provider
+        .resolver()
+        .with_reject_private(true)
+        .with_fallback(fallback);
+
+    let res = resolve(&resolver, "rebind.test").await;
+    assert!(matches!(res, Err(ResolveError::Denied { .. })), "{res:?}");
+}
+
+/// StaticCache always answers the same addrs.
+struct StaticCache(Vec<IpAddr>);
+
+impl crate::DnsCache for StaticCache {
+    fn get<'a>(&'a self, _: &'a str) -> futures::future::BoxFuture<'a, Option<Vec<IpAddr>>> {
+        Box::pin(futures::future::ready(Some(self.0.clone())))
+    }
+
+    fn put<'a>(
+        &'a self,
+        _: &'a str,
+        _: Vec<IpAddr>,
+        _: Duration,
+    ) -> futures::future::BoxFuture<'a, ()> {
+        Box::pin(futures::future::ready(()))
+    }
+}
+
+#[tokio::test]
+async fn test_empty_addrs() {
+    let provider = FakeProvider::default();
+    let resolver = provider.resolver().with_static_host("empty.test", vec![]);
+    let res = resolve(&resolver, "empty.test").await;
+    assert!(
+        matches!(res, Err(ResolveError::NoRecords { .. })),
+        "{res:?}"
+    );
+
+    let resolver = provider
+        .resolver()
+        .with_cache(Arc::new(StaticCache(vec![])))
+        .with_dedup(false);
+    let res = resolve(&resolver, "cached.test").await;
+    assert!(
+        matches!(res, Err(ResolveError::NoRecords { .. })),
+        "{res:?}"
+    );
+}
+
+#[tokio::test]
+async fn test_max_addrs_leaves_nothing() {
+    let provider = FakeProvider::default();
+    let resolver = provider
+        .resolver()
+        .with_static_host("a.test", vec!["10.0.0.1".parse().unwrap()]);
+
+    let res = resolve(&resolver.clone().with_max_addrs(0), "a.test").await;
+    assert!(
+        matches!(res, Err(ResolveError::NoRecords { .. })),
+        "{res:?}"
+    );
+    let res = resolve(&resolver.clone().with_max_addrs_per_family(0, 1), "a.test").await;
+    assert!(
+        matches!(res, Err(ResolveError::NoRecords { .. })),
+        "{res:?}"
+    );
+
+    let res = crate::HickoryResolverBuilder::from_provider(provider.clone())
+        .max_addrs(0)
+        .build();
+    assert!(matches!(res, Err(ResolveError::Config(_))), "{res:?}");
+    let res = crate::HickoryResolverBuilder::from_provider(provider.clone())
+        .max_addrs_per_family(0, 0)
+        .build();
+    assert!(matches!(res, Err(ResolveError::Config(_))), "{res:?}");
+    let res = crate::HickoryResolverBuilder::from_provider(provider)
+        .max_addrs_per_family(0, 1)
+        .build();
+    assert!(res.is_ok());
+}
+
+#[cfg(feature = "dnssec")]
+#[test]
+fn test_convert_dnssec_error() {
+    use hickory_resolver::proto::error::ProtoError;
+
+    let resolver = HickoryResolver::default().with_dnssec(true);
+    let convert = |err: ProtoError| resolver.convert_error(err.into());
+
+    let err = convert(ProtoError::from("validation failed"));
+    assert!(matches!(err, ResolveError::Dnssec(_)), "{err:?}");
+    assert!(!err.is_retryable());
+
+    // Other plain messages are not validation failures.
+    let err = convert(ProtoError::from("some other failure"));
+    assert!(matches!(err, ResolveError::Proto(_)), "{err:?}");
+    let mismatch = std::io::Error::new(std::io::ErrorKind::InvalidData, "0x20 mismatch");
+    let err = convert(ProtoError::from(mismatch));
+    assert!(matches!(err, ResolveError::Proto(_)), "{err:?}");
+    assert!(err.is_retryable());
+}
+
+#[tokio::test]
+async fn test_resolve_fresh_as_resolve() {
+    let provider = FakeProvider::default();
+    let resolved = Arc::new(Mutex::new(Vec::new()));
+    let hook_resolved = resolved.clone();
+    let resolver = provider
+        .resolver()
+        .with_name_rewrite(Arc::new(|name: &str| name.replace(".alias", ".test")))
+        .with_static_host("a.test", vec!["10.0.0.1".parse().unwrap()])
+        .with_on_resolve(Arc::new(move |name, addrs| {
+            hook_resolved
+                .lock()
+                .unwrap()
+                .push((name.as_str().to_string(), addrs.to_vec()));
+        }));
+
+    let addrs = resolver.resolve_fresh("a.alias").await.unwrap();
+    assert_eq!(addrs, vec!["10.0.0.1:0".parse().unwrap()]);
+    assert_eq!(
+        *resolved.lock().unwrap(),
+        vec![("a.test".to_string(), addrs)]
+    );
+    assert_eq!(provider.queries("a.test"), 0);
+}
+
+#[test]
+fn test_is_private() {
+    for ip in [
+        "0.0.0.0",
+        "10.0.0.1",
+        "127.0.0.1",
+        "169.254.169.254",
+        "::",
+        "::1",
+        "::ffff:0.0.0.0",
+        "::ffff:192.168.0.1",
+        "fe80::1",
+        "fd00::1",
+    ] {
+        assert!(crate::is_private(&ip.parse().unwrap()), "{ip}");
+    }
+    for ip in ["1.1.1.1", "2606:4700::1111", "::ffff:1.1.1.1"] {
+        assert!(!crate::is_private(&ip.parse().unwrap()), "{ip}");
+    }
+}
+
+#[tokio::test]
+async fn test_reject_private_unspecified() {
+    let provider = FakeProvider::default().with_record("rebind.test", &["0.0.0.0", "::"]);
+    let resolver = provider
+        .resolver()
+        .with_ip_strategy(LookupIpStrategy::Ipv4AndIpv6)
+        .with_reject_private(true);
+    let res = resolve(&resolver, "rebind.test").await;
+    assert!(matches!(res, Err(ResolveError::Denied { .. })), "{res:?}");
+}
+
+#[cfg(feature = "shuffle")]
+#[tokio::test]
+async fn test_shuffle_seed() {
+    let ips = (1..=16)
+        .map(|i| IpAddr::from([10, 0, 0, i]))
+        .collect::<Vec<_>>();
+    let resolver = || {
+        FakeProvider::default()
+            .resolver()
+            .with_static_host("a.test", ips.clone())
+            .with_shuffle_seed(42)
+    };
+
+    let (a, b) = (resolver(), resolver());
+    for _ in 0..3 {
+        let addrs = resolve(&a, "a.test").await.unwrap();
+        assert_eq!(addrs, resolve(&b, "a.test").await.unwrap());
+        assert_eq!(addrs.len(), ips.len());
+        // The chance of 16 addrs shuffled into the same order is negligible.
+        let unshuffled = ips.iter().map(|ip| SocketAddr::new(*ip, 0));
+        assert!(!addrs.into_iter().eq(unshuffled));
+    }
+}
+
+#[tokio::test]
+async fn test_family_preference() {
+    let provider = FakeProvider::default()
+        .with_record("dual.test", &["10.0.0.1", "fd00::1", "10.0.0.2", "fd00::2"]);
+    let resolver = |pref| {
+        provider
+            .resolver()
+            .with_ip_strategy(LookupIpStrategy::Ipv4AndIpv6)
+            .with_family_preference(pref)
+    };
+
+    let addrs = resolve(&resolver(FamilyPreference::Ipv6First), "dual.test").await;
+    assert_eq!(
+        addrs.unwrap(),
+        socket_addrs(&["fd00::1", "fd00::2", "10.0.0.1", "10.0.0.2"])
+    );
+    let addrs = resolve(&resolver(FamilyPreference::Ipv4First), "dual.test").await;
+    assert_eq!(
+        addrs.unwrap(),
+        socket_addrs(&["10.0.0.1", "10.0.0.2", "fd00::1", "fd00::2"])
+    );
+}
+
+#[tokio::test]
+async fn test_fallback() {
+    let primary = FakeProvider::default();
+    let secondary = FakeProvider::default();
+    let tertiary = FakeProvider::default().with_record("a.test", &["10.0.0.1"]);
+    let fallback = secondary
+        .resolver()
+        .with_ip_strategy(LookupIpStrategy::Ipv4Only)
+        .with_fallback(
+            tertiary
+                .resolver()
+                .with_ip_strategy(LookupIpStrategy::Ipv4Only)
+                .shared(),
+        );
+    let resolver = primary
+        .resolver()
+        .with_ip_strategy(LookupIpStrategy::Ipv4Only)
+        .with_fallback(fallback.shared());
+
+    let addrs = resolve(&resolver, "a.test").await.unwrap();
+    assert_eq!(addrs, socket_addrs(&["10.0.0.1"]));
+    // Each resolver in the chain is tried in order.
+    assert_eq!(primary.queries("a.test"), 1);
+    assert_eq!(secondary.queries("a.test"), 1);
+    assert_eq!(tertiary.queries("a.test"), 1);
+}
+
+#[tokio::test]
+async fn test_allowlist() {
+    let provider = FakeProvider::default()
+        .with_record("a.test", &["10.0.0.1"])
+        .with_record("b.test", &["10.0.0.2"])
+        .with_record("x.ok.test", &["10.0.0.3"]);
+    let resolver = provider
+        .resolver()
+        .with_ip_strategy(LookupIpStrategy::Ipv4Only)
+        .with_allowlist(vec!["A.test".to_string(), ".ok.test".to_string()]);
+
+    assert!(resolve(&resolver, "a.test").await.is_ok());
+    assert!(resolve(&resolver, "x.ok.test").await.is_ok());
+    let res = resolve(&resolver, "b.test").await;
+    assert!(matches!(res, Err(ResolveError::Blocked { .. })), "{res:?}");
+    assert_eq!(provider.queries("b.test"), 0);
+
+    // The blocklist takes precedence.
+    let resolver = resolver.with_blocklist(vec!["x.ok.test".to_string()]);
+    let res = resolve(&resolver, "x.ok.test").await;
+    assert!(matches!(res, Err(ResolveError::Blocked { .. })), "{res:?}");
+}
+
+#[tokio::test]
+async fn test_rotation() {
+    let provider =
+        FakeProvider::default().with_record("a.test", &["10.0.0.1", "10.0.0.2", "10.0.0.3"]);
+    let resolver = provider
+        .resolver()
+        .with_ip_strategy(LookupIpStrategy::Ipv4Only)
+        .with_rotation(true);
+
+    let mut last = resolve(&resolver, "a.test").await.unwrap();
+    let mut firsts = vec![last[0]];
+    for _ in 0..2 {
+        let addrs = resolve(&resolver, "a.test").await.unwrap();
+        // The relative order is kept while starting at the next addr.
+        last.rotate_left(1);
+        assert_eq!(addrs, last);
+        firsts.push(addrs[0]);
+    }
+    firsts.sort();
+    assert_eq!(firsts, socket_addrs(&["10.0.0.1", "10.0.0.2", "10.0.0.3"]));
+}
+
+#[tokio::test]
+async fn test_sorted() {
+    let provider =
+        FakeProvider::default().with_record("a.test", &["fd00::1", "10.0.0.2", "10.0.0.1"]);
+    let resolver = provider
+        .resolver()
+        .with_ip_strategy(LookupIpStrategy::Ipv4AndIpv6)
+        .with_sorted(true);
+
+    let addrs = resolve(&resolver, "a.test").await.unwrap();
+    assert_eq!(addrs, socket_addrs(&["10.0.0.1", "10.0.0.2", "fd00::1"]));
+}
+
+#[tokio::test]
+async fn test_interleave() {
+    let provider = FakeProvider::default().with_record(
+        "dual.test",
+        &["10.0.0.1", "10.0.0.2", "10.0.0.3", "fd00::1", "fd00::2"],
+    );
+    let resolver = provider
+        .resolver()
+        .with_ip_strategy(LookupIpStrategy::Ipv4AndIpv6)
+        .with_interleave(true);
+
+    let addrs = resolve(&resolver, "dual.test").await.unwrap();
+    assert_eq!(
+        addrs,
+        socket_addrs(&["fd00::1", "10.0.0.1", "fd00::2", "10.0.0.2", "10.0.0.3"])
+    );
+}
+
+#[tokio::test]
+async fn test_resolve_many() {
+    let provider = FakeProvider::default()
+        .with_record("a.test", &["10.0.0.1"])
+        .with_record("b.test", &["10.0.0.2"]);
+    let resolver = provider
+        .resolver()
+        .with_ip_strategy(LookupIpStrategy::Ipv4Only)
+        .with_max_concurrency(1);
+
+    let res = resolver
+        .resolve_many(&["a.test", "missing.test", "b.test"])
+        .await;
+    let names = res
+        .iter()
+        .map(|(name, _)| name.as_str())
+        .collect::<Vec<_>>();
+    assert_eq!(names, ["a.test", "missing.test", "b.test"]);
+    assert_eq!(res[0].1.as_ref().unwrap(), &socket_addrs(&["10.0.0.1"]));
+    assert!(
+        matches!(res[1].1, Err(ResolveError::NoRecords { .. })),
+        "{res:?}"
+    );
+    assert_eq!(res[2].1.as_ref().unwrap(), &socket_addrs(&["10.0.0.2"]));
+}
+
+#[tokio::test]
+async fn test_sticky() {
+    let ips = ["10.0.0.1", "10.0.0.2", "10.0.0.3", "10.0.0.4"];
+    let provider = FakeProvider::default().with_record("a.test", &ips);
+    let resolver = provider
+        .resolver()
+        .with_ip_strategy(LookupIpStrategy::Ipv4Only)
+        .with_sticky(true);
+
+    let preferred = resolve(&resolver, "a.test").await.unwrap()[0];
+    for _ in 0..3 {
+        assert_eq!(resolve(&resolver, "a.test").await.unwrap()[0], preferred);
+    }
+
+    // Another addr is preferred once the preferred one disappears.
+    let rest = ips
+        .into_iter()
+        .filter(|ip| preferred.ip() != ip.parse::<IpAddr>().unwrap())
+        .collect::<Vec<_>>();
+    provider.set_record("a.test", &rest);
+    resolver.clear_cache();
+    let next = resolve(&resolver, "a.test").await.unwrap()[0];
+    assert_ne!(next, preferred);
+    resolver.clear_cache();
+    assert_eq!(resolve(&resolver, "a.test").await.unwrap()[0], next);
+}
+
+#[tokio::test]
+async fn test_max_addrs_per_family() {
+    let provider = FakeProvider::default().with_record(
+        "dual.test",
+        &[
+            "10.0.0.1", "10.0.0.2", "10.0.0.3", "fd00::1", "fd00::2", "fd00::3",
+        ],
+    );
+    let resolver = provider
+        .resolver()
+        .with_ip_strategy(LookupIpStrategy::Ipv4AndIpv6)
+        .with_max_addrs_per_family(2, 1);
+
+    let addrs = resolve(&resolver, "dual.test").await.unwrap();
+    assert_eq!(addrs, socket_addrs(&["10.0.0.1", "10.0.0.2", "fd00::1"]));
+}
+
+#[tokio::test]
+async fn test_latency_feedback() {
+    let provider =
+        FakeProvider::default().with_record("a.test", &["10.0.0.1", "10.0.0.2", "10.0.0.3"]);
+    let resolver = provider
+        .resolver()
+        .with_ip_strategy(LookupIpStrategy::Ipv4Only)
+        .with_latency_feedback(true);
+
+    let ip = |s: &str| s.parse::<IpAddr>().unwrap();
+    resolver.record_latency(ip("10.0.0.1"), Duration::from_millis(100));
+    resolver.record_latency(ip("10.0.0.3"), Duration::from_millis(1));
+    // The unseen addr gets the average latency.
+    let addrs = resolve(&resolver, "a.test").await.unwrap();
+    assert_eq!(addrs, socket_addrs(&["10.0.0.3", "10.0.0.2", "10.0.0.1"]));
+}
+
+#[tokio::test]
+async fn test_min_addrs() {
+    let provider = FakeProvider::default()
+        .with_record("one.test", &["10.0.0.1"])
+        .with_record("two.test", &["10.0.0.1", "10.0.0.2"])
+        .with_record("mixed.test", &["10.0.0.1", "192.0.2.1"]);
+    let resolver = provider
+        .resolver()
+        .with_ip_strategy(LookupIpStrategy::Ipv4Only)
+        .with_min_addrs(2);
+
+    let res = resolve(&resolver, "one.test").await;
+    assert!(
+        matches!(
+            res,
+            Err(ResolveError::InsufficientRecords {
+                found: 1,
+                required: 2
+            })
+        ),
+        "{res:?}"
+    );
+    assert!(resolve(&resolver, "two.test").await.is_ok());
+
+    // The addrs are counted after being filtered.
+    let resolver = resolver.with_reject_private(true);
+    let res = resolve(&resolver, "mixed.test").await;
+    assert!(
+        matches!(res, Err(ResolveError::InsufficientRecords { found: 1, .. })),
+        "{res:?}"
+    );
+}
+
+#[tokio::test]
+async fn test_cache_index_trailing_dot() {
+    let provider = FakeProvider::default().with_record("a.test", &["10.0.0.1"]);
+    let resolver = provider
+        .resolver()
+        .with_ip_strategy(LookupIpStrategy::Ipv4Only);
+
+    resolve(&resolver, "a.test").await.unwrap();
+    resolve(&resolver, "A.test.").await.unwrap();
+    assert_eq!(resolver.cache_len(), 1);
+    assert!(resolver.is_cached_fresh("a.test."));
+    assert_eq!(
+        resolver.cache_stats(),
+        crate::CacheStats { hits: 1, misses: 1 }
+    );
+}
+
+#[tokio::test]
+async fn test_max_concurrency_of_zero() {
+    let provider = FakeProvider::default().with_record("a.test", &["10.0.0.1"]);
+    let resolver = provider.resolver().with_max_concurrency(0);
+
+    let res = tokio::time::timeout(Duration::from_secs(1), resolve(&resolver, "a.test")).await;
+    assert!(matches!(res, Ok(Err(ResolveError::Config(_)))), "{res:?}");
+    assert_eq!(provider.queries("a.test"), 0);
+}